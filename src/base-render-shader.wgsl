@@ -3,16 +3,8 @@ struct VertexOutput {
     [[builtin(position)]] pos: vec4<f32>;
 };
 
-[[block]]
-struct CellUniformData {
-    data: vec2<f32>;
-};
-
-[[group(0), binding(0)]]
-var<uniform> cell_data: CellUniformData;
-
 [[stage(vertex)]]
-fn base_main(
+fn vs_main(
     [[location(0)]] pos: vec2<f32>,
     [[location(1)]] tex_coor: vec2<f32>,
 ) -> VertexOutput {
@@ -23,10 +15,25 @@ fn base_main(
     return out;
 }
 
+struct BlitConfig {
+    // 1 for nearest, 0 for the bilinear filtering of the sampler
+    nearest: u32;
+    placholder0: u32;
+    placholder1: u32;
+    placholder2: u32;
+};
+
 [[group(0), binding(0)]] var tex_color: texture_2d<f32>;
 [[group(0), binding(1)]] var tex_sampler: sampler;
+[[group(0), binding(2)]] var<uniform> blit_config: BlitConfig;
 
 [[stage(fragment)]]
-fn base_main([[location(0)]] tex_coor: vec2<f32>) -> [[location(0)]] vec4<f32> {
-    return textureSample(tex_color, tex_sampler, tex_coor);
+fn fs_main([[location(0)]] tex_coor: vec2<f32>) -> [[location(0)]] vec4<f32> {
+    var coordinate: vec2<f32> = tex_coor;
+    if (blit_config.nearest != 0u) {
+        // the center of the texel, where bilinear filtering has no effect
+        let size = vec2<f32>(textureDimensions(tex_color));
+        coordinate = (floor(tex_coor * size) + 0.5) / size;
+    }
+    return textureSample(tex_color, tex_sampler, coordinate);
 }
@@ -1,61 +1,171 @@
 struct Sphere {
     position: vec4<f32>;
-    color: vec3<f32>;
     radius: f32;
+    material: u32;
+    placholder: vec2<f32>;
 };
 
+// parallelogram spanned by the edges from origin, facing along their cross
+// product
 struct Panel {
-    point0: vec4<f32>;
-    point1: vec4<f32>;
+    origin: vec4<f32>;
+    edge_u: vec4<f32>;
+    edge_v: vec4<f32>;
     normal: vec4<f32>;
-    color: vec3<f32>;
-    placholder: f32;
+    material: u32;
+    placholder0: f32;
+    placholder1: f32;
+    placholder2: f32;
 };
 
-struct PixelData {
-    col_row: vec2<f32>;
+struct Vertex {
+    position: vec4<f32>;
+    normal: vec4<f32>;
 };
 
-[[block]]
-struct InputBuffer {
-    data: [[stride(8)]] array<PixelData>;
+struct Triangle {
+    indices: vec3<u32>;
+    material: u32;
+};
+
+// values of `Material.kind`, see `entity::material_impl`
+let MATERIAL_LAMBERTIAN: u32 = 0u;
+let MATERIAL_METAL: u32 = 1u;
+let MATERIAL_DIELECTRIC: u32 = 2u;
+let MATERIAL_EMISSIVE: u32 = 3u;
+let MATERIAL_PBR: u32 = 4u;
+
+struct Material {
+    // albedo, or radiance for emissive materials
+    color: vec3<f32>;
+    kind: u32;
+    // fuzz for metals, index of refraction for dielectrics, roughness for pbr
+    parameter: f32;
+    metallic: f32;
 };
 
-[[block]]
-struct ResultBuffer {
-    data: [[stride(32)]] array<u32>;
+struct RadianceBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
 };
 
 [[group(0), binding(0)]]
-var<storage, read> input_list: InputBuffer;
+var<storage, read_write> radiance_list: RadianceBuffer;
+
+struct AccumulationBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+
 [[group(0), binding(1)]]
-var<storage, read_write> output_list: ResultBuffer;
+var<storage, read_write> accumulation_list: AccumulationBuffer;
+
+// three entries per pixel, see `systems::aov`
+struct AovBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+
+[[group(0), binding(2)]]
+var<storage, read_write> aov_list: AovBuffer;
 
-[[block]]
 struct SphereList {
-    data: [[stride(32)]] array<Sphere, 1>;
+    data: [[stride(32)]] array<Sphere>;
 };
 
 [[group(1), binding(0)]]
-var<uniform> sphere_list: SphereList;
+var<storage, read> sphere_list: SphereList;
 
-[[block]]
 struct PanelList {
-    data: [[stride(64)]] array<Panel, 5>;
+    data: [[stride(80)]] array<Panel>;
 };
 
 [[group(1), binding(1)]]
-var<uniform> panel_list: PanelList;
+var<storage, read> panel_list: PanelList;
 
-[[block]]
-struct LightList {
-    data: [[stride(64)]] array<Panel, 1>;
+[[group(1), binding(2)]]
+var<storage, read> light_list: PanelList;
+
+struct VertexList {
+    data: [[stride(32)]] array<Vertex>;
 };
 
-[[group(1), binding(2)]]
-var<uniform> light_list: LightList;
+[[group(1), binding(5)]]
+var<storage, read> vertex_list: VertexList;
+
+struct TriangleList {
+    data: [[stride(16)]] array<Triangle>;
+};
+
+[[group(1), binding(6)]]
+var<storage, read> triangle_list: TriangleList;
+
+// a range of the triangle list placed in the world, see `entity::Instance`
+struct Instance {
+    object_to_world: mat4x4<f32>;
+    world_to_object: mat4x4<f32>;
+    first_triangle: u32;
+    triangle_count: u32;
+    bvh_root: u32;
+    placholder: u32;
+};
+
+struct InstanceList {
+    data: [[stride(144)]] array<Instance>;
+};
+
+[[group(1), binding(14)]]
+var<storage, read> instance_list: InstanceList;
+
+struct BvhNode {
+    aabb_min: vec3<f32>;
+    left_or_first: u32;
+    aabb_max: vec3<f32>;
+    count: u32;
+};
+
+struct BvhNodeList {
+    data: [[stride(32)]] array<BvhNode>;
+};
+
+[[group(1), binding(7)]]
+var<storage, read> bvh_node_list: BvhNodeList;
+
+// kind << 30 | index, see `systems::bvh`
+struct BvhPrimitiveList {
+    data: [[stride(4)]] array<u32>;
+};
+
+[[group(1), binding(8)]]
+var<storage, read> bvh_primitive_list: BvhPrimitiveList;
+
+// values of `DeltaLight.kind`, see `entity::delta_light_impl`
+let LIGHT_POINT: u32 = 0u;
+let LIGHT_SPOT: u32 = 1u;
+let LIGHT_DIRECTIONAL: u32 = 2u;
+
+struct DeltaLight {
+    position: vec4<f32>;
+    direction: vec4<f32>;
+    // irradiance for directional lights
+    intensity: vec3<f32>;
+    kind: u32;
+    cos_inner: f32;
+    cos_outer: f32;
+    placholder: vec2<f32>;
+};
+
+struct DeltaLightList {
+    data: [[stride(64)]] array<DeltaLight>;
+};
+
+[[group(1), binding(10)]]
+var<storage, read> delta_light_list: DeltaLightList;
+
+struct MaterialList {
+    data: [[stride(32)]] array<Material>;
+};
+
+[[group(1), binding(9)]]
+var<storage, read> material_list: MaterialList;
 
-[[block]]
 struct ConfigData {
     seed: u32;
     window_width: u32;
@@ -64,20 +174,221 @@ struct ConfigData {
     sphere_count: u32;
     panel_count: u32;
     light_count: u32;
-    p: u32;
+    max_depth: u32;
+    frame_index: u32;
+    instance_count: u32;
+    bvh_node_count: u32;
+    russian_roulette_depth: u32;
+    delta_light_count: u32;
+    environment: u32;
+    environment_intensity: f32;
+    // radians around the y axis
+    environment_rotation: f32;
+    emitter_count: u32;
+    // sum of area times luminance over the emitter list
+    emitter_power: f32;
+    aov: u32;
+    // 0 independent, 1 stratified, 2 sobol, as `settings::Sampler`
+    sampler_kind: u32;
 };
 
 [[group(1), binding(3)]]
-var<storage, read> config_data: ConfigData;
+var<uniform> config_data: ConfigData;
+
+// equirectangular radiance, see `systems::environment`
+[[group(1), binding(11)]]
+var environment_texture: texture_2d<f32>;
+
+// average, grid width and height, marginal CDF, then one conditional CDF
+// per row of the grid
+struct EnvironmentCdf {
+    data: [[stride(4)]] array<f32>;
+};
+
+[[group(1), binding(12)]]
+var<storage, read> environment_cdf: EnvironmentCdf;
+
+// first entry of the marginal CDF
+let ENVIRONMENT_CDF_MARGINAL: u32 = 3u;
+
+// an emissive sphere, panel, light or triangle, see `systems::emitters`
+struct Emitter {
+    // kind << 30 | index, as in the bvh primitive list
+    primitive: u32;
+    // instance placing a triangle
+    instance: u32;
+    // probability of picking this or an earlier emitter
+    cdf: f32;
+};
+
+struct EmitterList {
+    data: [[stride(12)]] array<Emitter>;
+};
+
+[[group(1), binding(13)]]
+var<storage, read> emitter_list: EmitterList;
+
+struct Camera {
+    eye: vec4<f32>;
+    target: vec4<f32>;
+    up: vec3<f32>;
+    vfov: f32;
+    aspect: f32;
+};
+
+[[group(1), binding(4)]]
+var<uniform> camera: Camera;
 
 struct HitInfo {
     hit_point: vec4<f32>;
     hit_normal: vec4<f32>;
-    albedo: vec3<f32>;
     t: f32;
+    // index into the material list, -1 on a miss
     hit_material: i32;
+    // kind << 30 | index, as in the bvh primitive list
+    hit_primitive: u32;
+    // instance placing a hit triangle
+    hit_instance: u32;
 };
 
+// source: https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+fn pcg_hash(input: u32) -> u32 {
+    let state: u32 = input * 747796405u + 2891336453u;
+    let word: u32 = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+let SAMPLER_INDEPENDENT: u32 = 0u;
+let SAMPLER_STRATIFIED: u32 = 1u;
+let SAMPLER_SOBOL: u32 = 2u;
+
+// What the stratified and sobol samplers are keyed by besides the
+// dimension, which they keep in the rng state. Set by `sampler_begin`.
+var<private> sampler_pixel_seed: u32;
+var<private> sampler_index: u32;
+
+// [0, 1) from the high 24 bits, which a float holds exactly
+fn sample_to_float(bits: u32) -> f32 {
+    return f32(bits >> 8u) / 16777216.0;
+}
+
+// A random permutation of [0, length) chosen by `seed`, from Kensler's
+// "Correlated Multi-Jittered Sampling"
+fn permute(index: u32, length: u32, seed: u32) -> u32 {
+    var mask: u32 = length - 1u;
+    mask = mask | (mask >> 1u);
+    mask = mask | (mask >> 2u);
+    mask = mask | (mask >> 4u);
+    mask = mask | (mask >> 8u);
+    mask = mask | (mask >> 16u);
+    var i: u32 = index;
+    loop {
+        i = i ^ seed;
+        i = i * 0xe170893du;
+        i = i ^ (seed >> 16u);
+        i = i ^ ((i & mask) >> 4u);
+        i = i ^ (seed >> 8u);
+        i = i * 0x0929eb3fu;
+        i = i ^ (seed >> 23u);
+        i = i ^ ((i & mask) >> 1u);
+        i = i * (1u | (seed >> 27u));
+        i = i * 0x6935fa69u;
+        i = i ^ ((i & mask) >> 11u);
+        i = i * 0x74dcb303u;
+        i = i ^ ((i & mask) >> 2u);
+        i = i * 0x9e501cc3u;
+        i = i ^ ((i & mask) >> 2u);
+        i = i * 0xc860a3dfu;
+        i = i & mask;
+        i = i ^ (i >> 5u);
+        // cycle walking until the value is in range again
+        if (i < length) {
+            break;
+        }
+    }
+    return (i + seed) % length;
+}
+
+// Owen scrambling by hashing, from Burley's "Practical Hash-based Owen
+// Scrambling". Each bit is flipped depending only on the bits above it.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    var v: u32 = reverseBits(x);
+    v = v + seed;
+    v = v ^ (v * 0x6c50b47cu);
+    v = v ^ (v * 0xb82f1e52u);
+    v = v ^ (v * 0xc7afe638u);
+    v = v ^ (v * 0x8d22f6e6u);
+    return reverseBits(v);
+}
+
+// second Sobol dimension, the first is `reverseBits`
+fn sobol_1(index: u32) -> u32 {
+    var result: u32 = 0u;
+    var direction: u32 = 1u << 31u;
+    var i: u32 = index;
+    loop {
+        if (i == 0u) {
+            break;
+        }
+        if ((i & 1u) != 0u) {
+            result = result ^ direction;
+        }
+        i = i >> 1u;
+        direction = direction ^ (direction >> 1u);
+    }
+    return result;
+}
+
+fn sampler_seed(dimension: u32) -> u32 {
+    return pcg_hash(sampler_pixel_seed ^ pcg_hash(dimension));
+}
+
+// Every dimension shuffles the sample order on its own so that they do not
+// correlate, and scrambles the first two Sobol dimensions, which are well
+// distributed in 2D.
+fn sobol_sample(dimension: u32) -> vec2<f32> {
+    let seed = sampler_seed(dimension);
+    let index = nested_uniform_scramble(sampler_index, seed);
+    let x = nested_uniform_scramble(reverseBits(index), pcg_hash(seed));
+    let y = nested_uniform_scramble(sobol_1(index), pcg_hash(seed + 1u));
+    return vec2<f32>(sample_to_float(x), sample_to_float(y));
+}
+
+// The samples of a frame go to distinct cells of a grid, picked at random
+// when spp leaves some of them empty.
+fn stratified_sample_2(dimension: u32) -> vec2<f32> {
+    let spp = config_data.spp;
+    let n = sampler_index % spp;
+    let seed = pcg_hash(sampler_seed(dimension) ^ pcg_hash(sampler_index / spp));
+    let columns = u32(ceil(sqrt(f32(spp))));
+    let rows = (spp + columns - 1u) / columns;
+    let cell = permute(n, columns * rows, seed);
+    let jitter = pcg_hash(seed ^ n);
+    return vec2<f32>(
+        (f32(cell % columns) + sample_to_float(jitter)) / f32(columns),
+        (f32(cell / columns) + sample_to_float(pcg_hash(jitter))) / f32(rows),
+    );
+}
+
+fn stratified_sample_1(dimension: u32) -> f32 {
+    let spp = config_data.spp;
+    let n = sampler_index % spp;
+    let seed = pcg_hash(sampler_seed(dimension) ^ pcg_hash(sampler_index / spp));
+    return (f32(permute(n, spp, seed)) + sample_to_float(pcg_hash(seed ^ n))) / f32(spp);
+}
+
+// Starts sample `index` of the pixel, counted over every frame since the
+// last reset, and returns the rng state to draw it with.
+fn sampler_begin(pixel_index: u32, index: u32, rng_state: u32) -> u32 {
+    if (config_data.sampler_kind == SAMPLER_INDEPENDENT) {
+        return rng_state;
+    }
+    sampler_pixel_seed = pcg_hash(pixel_index ^ pcg_hash(config_data.seed));
+    sampler_index = index;
+    // the first dimension
+    return 0u;
+}
+
 struct RandGen1Res {
     rng_state: u32;
     number: f32;
@@ -85,6 +396,15 @@ struct RandGen1Res {
 // source: https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
 fn rand_float_generate(rng_state: u32) -> RandGen1Res {
     var res: RandGen1Res;
+    if (config_data.sampler_kind == SAMPLER_STRATIFIED) {
+        res.number = stratified_sample_1(rng_state);
+        res.rng_state = rng_state + 1u;
+        return res;
+    } else if (config_data.sampler_kind == SAMPLER_SOBOL) {
+        res.number = sobol_sample(rng_state).x;
+        res.rng_state = rng_state + 1u;
+        return res;
+    }
     let state: u32 = rng_state;
     res.rng_state = state * 747796405u + 2891336453u;
     var word: u32 = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
@@ -98,8 +418,22 @@ struct RandGen2Res {
     number1: f32;
 };
 
+// Both numbers come from one dimension of the stratified and sobol
+// samplers, which is stratified in 2D.
 fn rand_float_2_generate(rng_state: u32) -> RandGen2Res {
     var res: RandGen2Res;
+    if (config_data.sampler_kind != SAMPLER_INDEPENDENT) {
+        var numbers: vec2<f32>;
+        if (config_data.sampler_kind == SAMPLER_STRATIFIED) {
+            numbers = stratified_sample_2(rng_state);
+        } else {
+            numbers = sobol_sample(rng_state);
+        }
+        res.number0 = numbers.x;
+        res.number1 = numbers.y;
+        res.rng_state = rng_state + 1u;
+        return res;
+    }
     var state: u32 = rng_state;
     var word: u32 = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
     state = state * 747796405u + 2891336453u;
@@ -110,32 +444,130 @@ fn rand_float_2_generate(rng_state: u32) -> RandGen2Res {
     return res;
 }
 
-fn point_in_points(point: vec4<f32>, point0: vec4<f32>, point1: vec4<f32>, normal: vec4<f32>) -> bool {
-    let x_axis = vec4<f32>(1.0, 0.0, 0.0, 0.0);
-    let y_axis = vec4<f32>(0.0, 1.0, 0.0, 0.0);
-    let z_axis = vec4<f32>(0.0, 0.0, 1.0, 0.0);
-    if (abs(dot(x_axis, normal)) > 0.001) {
-        if (point0.y < point.y && point.y < point1.y && point0.z < point.z && point.z < point1.z) {
-            return true;
-        }
+// Whether a point in the plane of the panel lies within its edges.
+fn point_in_panel(point: vec4<f32>, panel: Panel) -> bool {
+    let n = cross(panel.edge_u.xyz, panel.edge_v.xyz);
+    let w = n / dot(n, n);
+    let local = (point - panel.origin).xyz;
+    let u = dot(w, cross(local, panel.edge_v.xyz));
+    let v = dot(w, cross(panel.edge_u.xyz, local));
+    return 0.0 <= u && u <= 1.0 && 0.0 <= v && v <= 1.0;
+}
+
+fn sphere_hit(sphere_index: u32, ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
+    var hit_rec: HitInfo;
+    hit_rec = hit_info;
+
+    let sphere = sphere_list.data[sphere_index];
+    let a = dot(ray_direction, ray_direction);
+    let oc = ray_origin - sphere.position;
+    let b = 2.0 * dot(oc, ray_direction);
+    let c = dot(oc, oc) - sphere.radius * sphere.radius;
+    let indicator = b * b - 4.0 * a * c;
+    if (indicator < 0.0) {
+        return hit_rec;
     }
-    if (abs(dot(y_axis, normal)) > 0.001) {
-        if (point0.x < point.x && point.x < point1.x && point0.z < point.z && point.z < point1.z) {
-            return true;
-        }
+    var sphere_t: f32 = (-b - sqrt(indicator)) / (2.0 * a);
+    if (sphere_t < 0.001) {
+        // the ray starts inside, e.g. after refracting into glass
+        sphere_t = (-b + sqrt(indicator)) / (2.0 * a);
+    }
+    if (sphere_t < 0.001) {
+        return hit_rec;
+    }
+    if (sphere_t < hit_rec.t) {
+        hit_rec.hit_point = ray_origin + sphere_t * ray_direction;
+        hit_rec.hit_normal = normalize(hit_rec.hit_point - sphere.position);
+        hit_rec.t = sphere_t;
+        hit_rec.hit_material = i32(sphere.material);
+        hit_rec.hit_primitive = sphere_index;
+    }
+
+    return hit_rec;
+}
+
+fn panel_hit(panel: Panel, primitive: u32, ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
+    var hit_rec: HitInfo;
+    hit_rec = hit_info;
+
+    var cos_dir_nor: f32 = dot(panel.normal, ray_direction);
+    if (cos_dir_nor > 0.0) {
+        return hit_rec;
+    }
+    var panel_t: f32;
+    var panel_hit_point: vec4<f32>;
+    panel_t = -dot(ray_origin - panel.origin, panel.normal) / dot(ray_direction, panel.normal);
+    panel_hit_point = ray_origin + panel_t * ray_direction;
+    if (panel_t < 0.0) {
+        return hit_rec;
+    }
+    let in_points = point_in_panel(panel_hit_point, panel);
+    if (panel_t < hit_rec.t && in_points) {
+        hit_rec.hit_point = ray_origin + panel_t * ray_direction;
+        hit_rec.hit_normal = panel.normal;
+        hit_rec.t = panel_t;
+        hit_rec.hit_material = i32(panel.material);
+        hit_rec.hit_primitive = primitive;
+    }
+
+    return hit_rec;
+}
+
+// Moller-Trumbore, hits both faces. Triangles are only hit in the object
+// space of an instance, so the caller passes the nearest `t` that still
+// clears the self-intersection distance in world space.
+fn triangle_hit(triangle_index: u32, ray_origin: vec4<f32>, ray_direction: vec4<f32>, t_min: f32, hit_info: HitInfo) -> HitInfo {
+    var hit_rec: HitInfo;
+    hit_rec = hit_info;
+
+    let triangle = triangle_list.data[triangle_index];
+    let v0 = vertex_list.data[triangle.indices.x];
+    let v1 = vertex_list.data[triangle.indices.y];
+    let v2 = vertex_list.data[triangle.indices.z];
+    let edge1 = (v1.position - v0.position).xyz;
+    let edge2 = (v2.position - v0.position).xyz;
+    let p = cross(ray_direction.xyz, edge2);
+    let det = dot(edge1, p);
+    if (abs(det) < 0.0000001) {
+        return hit_rec;
     }
-    if (abs(dot(z_axis, normal)) > 0.001) {
-        if (point0.x < point.x && point.x < point1.x && point0.y < point.y && point.y < point1.y) {
-            return true;
+    let inv_det = 1.0 / det;
+    let s = (ray_origin - v0.position).xyz;
+    let u = dot(s, p) * inv_det;
+    if (u < 0.0 || u > 1.0) {
+        return hit_rec;
+    }
+    let q = cross(s, edge1);
+    let v = dot(ray_direction.xyz, q) * inv_det;
+    if (v < 0.0 || u + v > 1.0) {
+        return hit_rec;
+    }
+    let triangle_t = dot(edge2, q) * inv_det;
+    if (triangle_t < t_min) {
+        return hit_rec;
+    }
+    if (triangle_t < hit_rec.t) {
+        var normal = (1.0 - u - v) * v0.normal + u * v1.normal + v * v2.normal;
+        if (dot(normal, normal) < 0.000001) {
+            normal = vec4<f32>(cross(edge1, edge2), 0.0);
         }
+        hit_rec.hit_point = ray_origin + triangle_t * ray_direction;
+        hit_rec.hit_normal = normalize(normal);
+        hit_rec.t = triangle_t;
+        hit_rec.hit_material = i32(triangle.material);
+        hit_rec.hit_primitive = (2u << 30u) | triangle_index;
     }
 
-    return false;
+    return hit_rec;
+}
+
+fn light_hit(light_index: u32, ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
+    return panel_hit(light_list.data[light_index], (3u << 30u) | light_index, ray_origin, ray_direction, hit_info);
 }
 
 fn sphere_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
-    let sphere_number: i32 = i32(config_data.sphere_count);
-    var sphere_index: i32 = 0;
+    let sphere_number: u32 = config_data.sphere_count;
+    var sphere_index: u32 = 0u;
     var hit_rec: HitInfo;
     hit_rec = hit_info;
     loop {
@@ -143,29 +575,10 @@ fn sphere_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info
             break;
         }
 
-        let sphere = sphere_list.data[sphere_index];
-        let a = dot(ray_direction, ray_direction);
-        let oc = ray_origin - sphere.position;
-        let b = 2.0 * dot(oc, ray_direction);
-        let c = dot(oc, oc) - sphere.radius * sphere.radius;
-        let indicator = b * b - 4.0 * a * c;
-        if (indicator < 0.0) {
-            continue;
-        }
-        let sphere_t = (-b - sqrt(indicator)) / (2.0 * a);
-        if (sphere_t < 0.0) {
-            continue;
-        }
-        if (sphere_t < hit_rec.t) {
-            hit_rec.hit_point = ray_origin + sphere_t * ray_direction;
-            hit_rec.hit_normal = normalize(hit_rec.hit_point - sphere.position);
-            hit_rec.albedo = sphere.color;
-            hit_rec.t = sphere_t;
-            hit_rec.hit_material = 5;
-        }
+        hit_rec = sphere_hit(sphere_index, ray_origin, ray_direction, hit_rec);
 
         continuing {
-            sphere_index = sphere_index + 1;
+            sphere_index = sphere_index + 1u;
         }
     }
 
@@ -173,8 +586,8 @@ fn sphere_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info
 }
 
 fn panel_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
-    let panel_number: i32 = i32(config_data.panel_count);
-    var panel_index: i32 = 0;
+    let panel_number: u32 = config_data.panel_count;
+    var panel_index: u32 = 0u;
     var hit_rec: HitInfo;
     hit_rec = hit_info;
     loop {
@@ -182,70 +595,269 @@ fn panel_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info:
             break;
         }
 
-        let panel = panel_list.data[panel_index];
-        var cos_dir_nor: f32 = dot(panel.normal, ray_direction);
-        if (cos_dir_nor > 0.0) {
-            continue;
+        hit_rec = panel_hit(panel_list.data[panel_index], (1u << 30u) | panel_index, ray_origin, ray_direction, hit_rec);
+
+        continuing {
+            panel_index = panel_index + 1u;
+        }
+    }
+
+    return hit_rec;
+}
+
+fn light_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
+    let light_number: u32 = config_data.light_count;
+    var light_index: u32 = 0u;
+    var hit_rec: HitInfo;
+    hit_rec = hit_info;
+    loop {
+        if (light_index >= light_number) {
+            break;
+        }
+
+        hit_rec = light_hit(light_index, ray_origin, ray_direction, hit_rec);
+
+        continuing {
+            light_index = light_index + 1u;
         }
-        var panel_t: f32;
-        var panel_hit_point: vec4<f32>;
-        panel_t = -dot(ray_origin - panel.point0, panel.normal) / dot(ray_direction, panel.normal);
-        panel_hit_point = ray_origin + panel_t * ray_direction;
-        if (panel_t < 0.0) {
+    }
+
+    return hit_rec;
+}
+
+// Distance to the box along the ray, or 1e30 when it is missed or farther
+// than `t_max`.
+fn aabb_distance(aabb_min: vec3<f32>, aabb_max: vec3<f32>, ray_origin: vec3<f32>, inv_direction: vec3<f32>, t_max: f32) -> f32 {
+    let t0 = (aabb_min - ray_origin) * inv_direction;
+    let t1 = (aabb_max - ray_origin) * inv_direction;
+    let t_small = min(t0, t1);
+    let t_big = max(t0, t1);
+    let t_near = max(max(t_small.x, t_small.y), t_small.z);
+    let t_far = min(min(t_big.x, t_big.y), t_big.z);
+    if (t_near <= t_far && t_far > 0.0 && t_near < t_max) {
+        return max(t_near, 0.0);
+    }
+    return 1000000000000000000000000000000.0;
+}
+
+// `BVH_STACK_SIZE`, the entries of the traversal stacks, is prepended to
+// this source by the tracer from `systems::bvh::BVH_STACK_SIZE`, the same
+// constant that bounds how deep the builder subdivides. Every hierarchy,
+// the top level one and each mesh one, is walked with a stack of its own.
+
+// The walk of `bvh_intersection` through the hierarchy of one mesh, whose
+// leaves only hold triangles.
+fn mesh_bvh_intersection(root_index: u32, ray_origin: vec4<f32>, ray_direction: vec4<f32>, t_min: f32, hit_info: HitInfo) -> HitInfo {
+    var hit_rec: HitInfo;
+    hit_rec = hit_info;
+    let inv_direction = 1.0 / ray_direction.xyz;
+
+    let root = bvh_node_list.data[root_index];
+    if (aabb_distance(root.aabb_min, root.aabb_max, ray_origin.xyz, inv_direction, hit_rec.t) > 100000000000000000000000000000.0) {
+        return hit_rec;
+    }
+
+    // nodes still to visit, at most one per level below the root
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size: u32 = 0u;
+    var node_index: u32 = root_index;
+    loop {
+        let node = bvh_node_list.data[node_index];
+        if (node.count > 0u) {
+            var primitive_index: u32 = 0u;
+            loop {
+                if (primitive_index >= node.count) {
+                    break;
+                }
+
+                let primitive = bvh_primitive_list.data[node.left_or_first + primitive_index];
+                hit_rec = triangle_hit(primitive & 1073741823u, ray_origin, ray_direction, t_min, hit_rec);
+
+                continuing {
+                    primitive_index = primitive_index + 1u;
+                }
+            }
+
+            if (stack_size == 0u) {
+                break;
+            }
+            stack_size = stack_size - 1u;
+            node_index = stack[stack_size];
             continue;
         }
-        // hitpoint in points
-        let in_points = point_in_points(panel_hit_point, panel.point0, panel.point1, panel.normal);
-        if (panel_t < hit_rec.t && in_points) {
-            hit_rec.hit_point = ray_origin + panel_t * ray_direction;
-            hit_rec.hit_normal = panel.normal;
-            hit_rec.albedo = panel.color;
-            hit_rec.t = panel_t;
-            hit_rec.hit_material = 5;
+
+        var near_index: u32 = node.left_or_first;
+        var far_index: u32 = node.left_or_first + 1u;
+        let near_node = bvh_node_list.data[near_index];
+        let far_node = bvh_node_list.data[far_index];
+        var near_t: f32 = aabb_distance(near_node.aabb_min, near_node.aabb_max, ray_origin.xyz, inv_direction, hit_rec.t);
+        var far_t: f32 = aabb_distance(far_node.aabb_min, far_node.aabb_max, ray_origin.xyz, inv_direction, hit_rec.t);
+        if (far_t < near_t) {
+            let temp_index = near_index;
+            near_index = far_index;
+            far_index = temp_index;
+            let temp_t = near_t;
+            near_t = far_t;
+            far_t = temp_t;
         }
 
-        continuing {
-            panel_index = panel_index + 1;
+        if (near_t > 100000000000000000000000000000.0) {
+            if (stack_size == 0u) {
+                break;
+            }
+            stack_size = stack_size - 1u;
+            node_index = stack[stack_size];
+        } else {
+            node_index = near_index;
+            // a node deeper than the stack is never built, so the bound only
+            // keeps a broken tree from writing past the end
+            if (far_t < 100000000000000000000000000000.0 && stack_size < BVH_STACK_SIZE) {
+                stack[stack_size] = far_index;
+                stack_size = stack_size + 1u;
+            }
         }
     }
 
     return hit_rec;
 }
 
-fn light_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
-    let panel_number: i32 = i32(config_data.panel_count);
-    var panel_index: i32 = 0;
+// Moves the ray into the object space of the instance, where its triangles
+// are stored. The direction keeps the length it gets there, so `t` means the
+// same in both spaces, and a world distance is `t` times the length of the
+// world direction whatever the instance scale.
+fn instance_hit(instance_index: u32, ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
+    let instance = instance_list.data[instance_index];
+    let object_origin = instance.world_to_object * ray_origin;
+    let object_direction = instance.world_to_object * ray_direction;
+    // hits closer than 0.001 world units are the surface the ray left, as
+    // for spheres and panels
+    let t_min = 0.001 / length(ray_direction.xyz);
+    var hit_rec: HitInfo = hit_info;
+    if (config_data.bvh_node_count > 0u) {
+        hit_rec = mesh_bvh_intersection(instance.bvh_root, object_origin, object_direction, t_min, hit_rec);
+    } else {
+        var triangle_index: u32 = instance.first_triangle;
+        loop {
+            if (triangle_index >= instance.first_triangle + instance.triangle_count) {
+                break;
+            }
+
+            hit_rec = triangle_hit(triangle_index, object_origin, object_direction, t_min, hit_rec);
+
+            continuing {
+                triangle_index = triangle_index + 1u;
+            }
+        }
+    }
+
+    if (hit_rec.t < hit_info.t) {
+        hit_rec.hit_point = ray_origin + hit_rec.t * ray_direction;
+        // normals go back with the inverse transpose
+        let world_normal = transpose(instance.world_to_object) * vec4<f32>(hit_rec.hit_normal.xyz, 0.0);
+        hit_rec.hit_normal = vec4<f32>(normalize(world_normal.xyz), 0.0);
+        hit_rec.hit_instance = instance_index;
+    }
+    return hit_rec;
+}
+
+fn instance_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo) -> HitInfo {
+    let instance_number: u32 = config_data.instance_count;
+    var instance_index: u32 = 0u;
     var hit_rec: HitInfo;
     hit_rec = hit_info;
     loop {
-        if (panel_index >= panel_number) {
+        if (instance_index >= instance_number) {
             break;
         }
 
-        let panel = light_list.data[panel_index];
-        var cos_dir_nor: f32 = dot(panel.normal, ray_direction);
-        if (cos_dir_nor > 0.0) {
-            continue;
+        hit_rec = instance_hit(instance_index, ray_origin, ray_direction, hit_rec);
+
+        continuing {
+            instance_index = instance_index + 1u;
         }
-        var panel_t: f32;
-        var panel_hit_point: vec4<f32>;
-        panel_t = -dot(ray_origin - panel.point0, panel.normal) / dot(ray_direction, panel.normal);
-        panel_hit_point = ray_origin + panel_t * ray_direction;
-        if (panel_t < 0.0) {
+    }
+
+    return hit_rec;
+}
+
+fn bvh_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info: HitInfo, with_lights: bool) -> HitInfo {
+    var hit_rec: HitInfo;
+    hit_rec = hit_info;
+    let inv_direction = 1.0 / ray_direction.xyz;
+
+    let root = bvh_node_list.data[0];
+    if (aabb_distance(root.aabb_min, root.aabb_max, ray_origin.xyz, inv_direction, hit_rec.t) > 100000000000000000000000000000.0) {
+        return hit_rec;
+    }
+
+    // nodes still to visit, at most one per level below the root
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size: u32 = 0u;
+    var node_index: u32 = 0u;
+    loop {
+        let node = bvh_node_list.data[node_index];
+        if (node.count > 0u) {
+            var primitive_index: u32 = 0u;
+            loop {
+                if (primitive_index >= node.count) {
+                    break;
+                }
+
+                let primitive = bvh_primitive_list.data[node.left_or_first + primitive_index];
+                let kind = primitive >> 30u;
+                let index = primitive & 1073741823u;
+                if (kind == 0u) {
+                    hit_rec = sphere_hit(index, ray_origin, ray_direction, hit_rec);
+                } else if (kind == 1u) {
+                    hit_rec = panel_hit(panel_list.data[index], primitive, ray_origin, ray_direction, hit_rec);
+                } else if (kind == 2u) {
+                    hit_rec = instance_hit(index, ray_origin, ray_direction, hit_rec);
+                } else if (with_lights) {
+                    hit_rec = light_hit(index, ray_origin, ray_direction, hit_rec);
+                }
+
+                continuing {
+                    primitive_index = primitive_index + 1u;
+                }
+            }
+
+            if (stack_size == 0u) {
+                break;
+            }
+            stack_size = stack_size - 1u;
+            node_index = stack[stack_size];
             continue;
         }
-        // hitpoint in points
-        let in_points = point_in_points(panel_hit_point, panel.point0, panel.point1, panel.normal);
-        if (panel_t < hit_rec.t && in_points) {
-            hit_rec.hit_point = ray_origin + panel_t * ray_direction;
-            hit_rec.hit_normal = panel.normal;
-            hit_rec.albedo = panel.color;
-            hit_rec.t = panel_t;
-            hit_rec.hit_material = 105;
+
+        var near_index: u32 = node.left_or_first;
+        var far_index: u32 = node.left_or_first + 1u;
+        let near_node = bvh_node_list.data[near_index];
+        let far_node = bvh_node_list.data[far_index];
+        var near_t: f32 = aabb_distance(near_node.aabb_min, near_node.aabb_max, ray_origin.xyz, inv_direction, hit_rec.t);
+        var far_t: f32 = aabb_distance(far_node.aabb_min, far_node.aabb_max, ray_origin.xyz, inv_direction, hit_rec.t);
+        if (far_t < near_t) {
+            let temp_index = near_index;
+            near_index = far_index;
+            far_index = temp_index;
+            let temp_t = near_t;
+            near_t = far_t;
+            far_t = temp_t;
         }
 
-        continuing {
-            panel_index = panel_index + 1;
+        if (near_t > 100000000000000000000000000000.0) {
+            if (stack_size == 0u) {
+                break;
+            }
+            stack_size = stack_size - 1u;
+            node_index = stack[stack_size];
+        } else {
+            node_index = near_index;
+            // a node deeper than the stack is never built, so the bound only
+            // keeps a broken tree from writing past the end
+            if (far_t < 100000000000000000000000000000.0 && stack_size < BVH_STACK_SIZE) {
+                stack[stack_size] = far_index;
+                stack_size = stack_size + 1u;
+            }
         }
     }
 
@@ -255,14 +867,20 @@ fn light_intersection(ray_origin: vec4<f32>, ray_direction: vec4<f32>, hit_info:
 fn ray_intersect(ray_origin: vec4<f32>, ray_direction: vec4<f32>) -> HitInfo {
     var hit_rec: HitInfo;
     hit_rec.t = 1000000000.0;
-    hit_rec.albedo = vec3<f32>(0.0, 0.0, 0.0);
     hit_rec.hit_material = -1;
+    if (config_data.bvh_node_count > 0u) {
+        return bvh_intersection(ray_origin, ray_direction, hit_rec, true);
+    }
+
     // sphere intersect
     hit_rec = sphere_intersection(ray_origin, ray_direction, hit_rec);
 
     // panel intersection
     hit_rec = panel_intersection(ray_origin, ray_direction, hit_rec);
 
+    // mesh intersection
+    hit_rec = instance_intersection(ray_origin, ray_direction, hit_rec);
+
     // light intersection
     hit_rec = light_intersection(ray_origin, ray_direction, hit_rec);
 
@@ -272,14 +890,20 @@ fn ray_intersect(ray_origin: vec4<f32>, ray_direction: vec4<f32>) -> HitInfo {
 fn ray_intersect_without_light(ray_origin: vec4<f32>, ray_direction: vec4<f32>) -> HitInfo {
     var hit_rec: HitInfo;
     hit_rec.t = 1000000000.0;
-    hit_rec.albedo = vec3<f32>(0.0, 0.0, 0.0);
     hit_rec.hit_material = -1;
+    if (config_data.bvh_node_count > 0u) {
+        return bvh_intersection(ray_origin, ray_direction, hit_rec, false);
+    }
+
     // sphere intersect
     hit_rec = sphere_intersection(ray_origin, ray_direction, hit_rec);
 
     // panel intersection
     hit_rec = panel_intersection(ray_origin, ray_direction, hit_rec);
 
+    // mesh intersection
+    hit_rec = instance_intersection(ray_origin, ray_direction, hit_rec);
+
     return hit_rec;
 }
 
@@ -341,333 +965,820 @@ fn rotate_vec_given_normal(temp_vec: vec4<f32>, normal: vec4<f32>) -> vec4<f32>
 
 struct ScatterResult {
     dir: vec4<f32>;
-    pdf: f32;
+    // brdf * cos / pdf for `dir`, what the light arriving along it is scaled by
+    weight: vec3<f32>;
     rng_state: u32;
+    // solid angle density of `dir`, 0 for the perfectly specular materials
+    pdf: f32;
 };
 
-fn generate_scatter_ray_dir(hit_info: HitInfo, rng_state: u32) -> ScatterResult {
+fn reflect_dir(dir: vec4<f32>, normal: vec4<f32>) -> vec4<f32> {
+    return dir - 2.0 * dot(dir, normal) * normal;
+}
+
+// `normal` faces the incoming ray; `ratio` is the index of refraction on the
+// incoming side over the one on the far side.
+fn refract_dir(dir: vec4<f32>, normal: vec4<f32>, ratio: f32) -> vec4<f32> {
+    let cos_theta = min(dot(-dir, normal), 1.0);
+    let perpendicular = ratio * (dir + cos_theta * normal);
+    let parallel = -sqrt(abs(1.0 - dot(perpendicular, perpendicular))) * normal;
+    return perpendicular + parallel;
+}
+
+fn cosine_sample_dir(normal: vec4<f32>, a: f32, b: f32) -> vec4<f32> {
+    let sin_theta = sqrt(a);
+    let cos_theta = sqrt(1.0 - a);
+    let sin_phi = sin(2.0 * 3.141592653 * b);
+    let cos_phi = cos(2.0 * 3.141592653 * b);
+    let temp_dir = vec4<f32>(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi, 0.0);
+
+    return rotate_vec_given_normal(temp_dir, normal);
+}
+
+// Columns are tangent, bitangent and normal, so the matrix takes local
+// directions to world space and its transpose takes them back.
+// source: Duff et al., "Building an Orthonormal Basis, Revisited"
+fn tangent_frame(normal: vec3<f32>) -> mat3x3<f32> {
+    var sign: f32 = 1.0;
+    if (normal.z < 0.0) {
+        sign = -1.0;
+    }
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    let tangent = vec3<f32>(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x);
+    let bitangent = vec3<f32>(b, sign + normal.y * normal.y * a, -normal.y);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+fn ggx_alpha(roughness: f32) -> f32 {
+    return max(roughness * roughness, 0.001);
+}
+
+fn ggx_d(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (3.141592653 * d * d);
+}
+
+// Smith masking of one direction, the full term is G1(wo) * G1(wi)
+fn ggx_g1(n_dot_v: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    return 2.0 * n_dot_v / (n_dot_v + sqrt(a2 + (1.0 - a2) * n_dot_v * n_dot_v));
+}
+
+fn fresnel_schlick(f0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return f0 + (vec3<f32>(1.0, 1.0, 1.0) - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// dielectrics reflect 4% at normal incidence, metals their base color
+fn pbr_f0(material: Material) -> vec3<f32> {
+    return (1.0 - material.metallic) * vec3<f32>(0.04, 0.04, 0.04) + material.metallic * material.color;
+}
+
+// Cook-Torrance GGX lobe over a Lambertian base that gets what the
+// specular lobe does not reflect. Returns brdf * cos, both directions
+// point away from the surface.
+fn eval_pbr(material: Material, wo: vec3<f32>, wi: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = dot(normal, wi);
+    let n_dot_v = dot(normal, wo);
+    if (n_dot_l <= 0.0 || n_dot_v <= 0.0) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    let alpha = ggx_alpha(material.parameter);
+    let h = normalize(wo + wi);
+    let n_dot_h = max(dot(normal, h), 0.0);
+    let fresnel = fresnel_schlick(pbr_f0(material), dot(wi, h));
+    let specular = fresnel * ggx_d(n_dot_h, alpha) * ggx_g1(n_dot_v, alpha) * ggx_g1(n_dot_l, alpha) / (4.0 * n_dot_v * n_dot_l);
+    let diffuse = (vec3<f32>(1.0, 1.0, 1.0) - fresnel) * (1.0 - material.metallic) * material.color / 3.141592653;
+    return (specular + diffuse) * n_dot_l;
+}
+
+// Brdf * cos for light sampling; zero for the perfectly specular
+// materials, which only ever scatter into one direction.
+fn eval_brdf_cos(material: Material, wo: vec3<f32>, wi: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    if (material.kind == MATERIAL_LAMBERTIAN) {
+        return material.color * max(dot(normal, wi), 0.0) / 3.141592653;
+    }
+    if (material.kind == MATERIAL_PBR) {
+        return eval_pbr(material, wo, wi, normal);
+    }
+    return vec3<f32>(0.0, 0.0, 0.0);
+}
+
+// How often the specular lobe is sampled, by its share of the reflectance.
+fn pbr_specular_probability(material: Material, n_dot_v: f32) -> f32 {
+    let luminance = vec3<f32>(0.2126, 0.7152, 0.0722);
+    let specular = dot(fresnel_schlick(pbr_f0(material), n_dot_v), luminance);
+    let diffuse = dot((1.0 - material.metallic) * material.color, luminance);
+    if (specular + diffuse <= 0.0) {
+        return 0.5;
+    }
+    return clamp(specular / (specular + diffuse), 0.1, 1.0);
+}
+
+// Density of `wi` over both lobes, the specular one sampled from the visible
+// normals.
+fn pbr_pdf(material: Material, wo: vec3<f32>, wi: vec3<f32>, normal: vec3<f32>, specular_probability: f32) -> f32 {
+    let n_dot_l = dot(normal, wi);
+    let n_dot_v = dot(normal, wo);
+    if (n_dot_l <= 0.0 || n_dot_v <= 0.0) {
+        return 0.0;
+    }
+    let alpha = ggx_alpha(material.parameter);
+    let h = normalize(wo + wi);
+    let n_dot_h = max(dot(normal, h), 0.0);
+    let specular_pdf = ggx_g1(n_dot_v, alpha) * ggx_d(n_dot_h, alpha) / (4.0 * n_dot_v);
+    let diffuse_pdf = n_dot_l / 3.141592653;
+    return specular_probability * specular_pdf + (1.0 - specular_probability) * diffuse_pdf;
+}
+
+// Density `generate_scatter_ray_dir` would have picked `wi` with.
+fn brdf_pdf(material: Material, wo: vec3<f32>, wi: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if (material.kind == MATERIAL_LAMBERTIAN) {
+        return max(dot(normal, wi), 0.0) / 3.141592653;
+    }
+    if (material.kind == MATERIAL_PBR) {
+        let specular_probability = pbr_specular_probability(material, max(dot(normal, wo), 0.0001));
+        return pbr_pdf(material, wo, wi, normal, specular_probability);
+    }
+    return 0.0;
+}
+
+// Samples a microfacet normal seen from `wo`, both in the local frame with
+// the surface normal along z.
+// source: Heitz, "Sampling the GGX Distribution of Visible Normals", JCGT 2018
+fn sample_ggx_vndf(wo: vec3<f32>, alpha: f32, u1: f32, u2: f32) -> vec3<f32> {
+    let vh = normalize(vec3<f32>(alpha * wo.x, alpha * wo.y, wo.z));
+    let length_square = vh.x * vh.x + vh.y * vh.y;
+    var t1: vec3<f32> = vec3<f32>(1.0, 0.0, 0.0);
+    if (length_square > 0.0) {
+        t1 = vec3<f32>(-vh.y, vh.x, 0.0) / sqrt(length_square);
+    }
+    let t2 = cross(vh, t1);
+    let r = sqrt(u1);
+    let phi = 2.0 * 3.141592653 * u2;
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+    let nh = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+    return normalize(vec3<f32>(alpha * nh.x, alpha * nh.y, max(0.0, nh.z)));
+}
+
+// `normal` faces the incoming ray, `front_face` tells whether it had to be
+// flipped for that.
+fn generate_scatter_ray_dir(material: Material, ray_direction: vec4<f32>, normal: vec4<f32>, front_face: bool, rng_state: u32) -> ScatterResult {
     var state: u32 = rng_state;
-    if (hit_info.hit_material < 10) {
-        var result: ScatterResult;
+    var result: ScatterResult;
+    if (material.kind == MATERIAL_LAMBERTIAN) {
+        let rand2_res = rand_float_2_generate(state);
+        state = rand2_res.rng_state;
+
+        // cosine sampling cancels the cosine and the 1 / pi of the brdf
+        result.dir = cosine_sample_dir(normal, rand2_res.number0, rand2_res.number1);
+        result.weight = material.color;
+        result.pdf = max(dot(result.dir, normal), 0.0) / 3.141592653;
+        result.rng_state = state;
 
+        return result;
+    }
+    if (material.kind == MATERIAL_PBR) {
+        let rand_res = rand_float_generate(state);
+        state = rand_res.rng_state;
         let rand2_res = rand_float_2_generate(state);
         state = rand2_res.rng_state;
-        let a: f32 = rand2_res.number0;
-        let b: f32 = rand2_res.number1;
 
-        let sin_theta = sqrt(a);
-        let cos_theta = sqrt(1.0 - a);
-        let sin_phi = sin(2.0 * 3.141592653 * b);
-        let cos_phi = cos(2.0 * 3.141592653 * b);
-        let pdf = cos_theta / 3.141592653;
-        let temp_dir = vec4<f32>(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi, 0.0);
+        let wo = -ray_direction.xyz;
+        let n = normal.xyz;
+        let specular_probability = pbr_specular_probability(material, max(dot(n, wo), 0.0001));
+        var wi: vec3<f32>;
+        if (rand_res.number < specular_probability) {
+            let frame = tangent_frame(n);
+            let h = frame * sample_ggx_vndf(transpose(frame) * wo, ggx_alpha(material.parameter), rand2_res.number0, rand2_res.number1);
+            wi = 2.0 * dot(wo, h) * h - wo;
+        } else {
+            wi = cosine_sample_dir(normal, rand2_res.number0, rand2_res.number1).xyz;
+        }
 
-        let dir = rotate_vec_given_normal(temp_dir, hit_info.hit_normal);
-        // let dir = hit_info.hit_normal;
+        let pdf = pbr_pdf(material, wo, wi, n, specular_probability);
+        result.dir = vec4<f32>(wi, 0.0);
+        result.weight = vec3<f32>(0.0, 0.0, 0.0);
+        if (pdf > 0.0) {
+            result.weight = eval_pbr(material, wo, wi, n) / pdf;
+        }
+        result.pdf = pdf;
+        result.rng_state = state;
 
+        return result;
+    }
+    if (material.kind == MATERIAL_METAL) {
+        let rand2_res = rand_float_2_generate(state);
+        state = rand2_res.rng_state;
+        // uniform direction on the unit sphere
+        let z = 1.0 - 2.0 * rand2_res.number0;
+        let r = sqrt(max(1.0 - z * z, 0.0));
+        let phi = 2.0 * 3.141592653 * rand2_res.number1;
+        let fuzz_dir = vec4<f32>(r * cos(phi), r * sin(phi), z, 0.0);
+
+        let reflected = reflect_dir(ray_direction, normal);
+        var dir: vec4<f32> = normalize(reflected + material.parameter * fuzz_dir);
+        if (dot(dir, normal) <= 0.0) {
+            dir = reflected;
+        }
         result.dir = dir;
-        result.pdf = cos_theta / 3.141592653;
+        result.weight = material.color;
+        result.pdf = 0.0;
+        result.rng_state = state;
+
+        return result;
+    }
+    if (material.kind == MATERIAL_DIELECTRIC) {
+        let rand_res = rand_float_generate(state);
+        state = rand_res.rng_state;
+
+        var ratio: f32 = material.parameter;
+        if (front_face) {
+            ratio = 1.0 / material.parameter;
+        }
+        let cos_theta = min(dot(-ray_direction, normal), 1.0);
+        let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+        // Schlick's approximation of the Fresnel reflectance
+        let r0 = (1.0 - ratio) / (1.0 + ratio);
+        let reflectance = r0 * r0 + (1.0 - r0 * r0) * pow(1.0 - cos_theta, 5.0);
+        if (ratio * sin_theta > 1.0 || rand_res.number < reflectance) {
+            result.dir = reflect_dir(ray_direction, normal);
+        } else {
+            result.dir = normalize(refract_dir(ray_direction, normal, ratio));
+        }
+        result.weight = material.color;
+        result.pdf = 0.0;
         result.rng_state = state;
 
         return result;
     }
-    var result: ScatterResult;
     result.dir = vec4<f32>(0.0, 1.0, 0.0, 0.0);
-    result.pdf = 1.0;
+    result.weight = vec3<f32>(0.0, 0.0, 0.0);
+    result.pdf = 0.0;
     result.rng_state = state;
 
     return result;
 }
 
-struct LightShadingData {
-    sample_point: vec4<f32>;
-    pdf_mul: f32;
-    rng_state: u32;
-};
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
 
-fn light_get_direct_shading_data(panel_point0: vec4<f32>, panel_point1: vec4<f32>, panel_normal: vec4<f32>, rng_state: u32) -> LightShadingData {
-    var state: u32 = rng_state;
-    var pdf_mul: f32 = -1.0;
-    var sample_point: vec4<f32>;
-    var res: LightShadingData;
-    if (abs(panel_normal.x) > 0.0) {
-        let y_width = panel_point1.y - panel_point0.y;
-        let z_width = panel_point1.z - panel_point0.z;
+fn primitive_panel(primitive: u32) -> Panel {
+    let index = primitive & 1073741823u;
+    if ((primitive >> 30u) == 3u) {
+        return light_list.data[index];
+    }
+    return panel_list.data[index];
+}
 
-        let rand2_res = rand_float_2_generate(state);
-        state = rand2_res.rng_state;
-        let y_sample = rand2_res.number0 * y_width + panel_point0.y;
-        let z_sample = rand2_res.number1 * z_width + panel_point0.z;
+// Solid angle density of light sampling at `origin` reaching `point` on an
+// emitter shining `emission`. Emitters are picked in proportion to area
+// times luminance, so the area of the picked one cancels out for the
+// shapes that are sampled by area.
+fn emitter_pdf(primitive: u32, instance_index: u32, origin: vec4<f32>, point: vec4<f32>, emission: vec3<f32>) -> f32 {
+    if (config_data.emitter_power <= 0.0) {
+        return 0.0;
+    }
+    let select_per_area = max(luminance(emission), 0.0) / config_data.emitter_power;
+    let kind = primitive >> 30u;
+    let index = primitive & 1073741823u;
+    let to_point = point - origin;
+    let distance_square = dot(to_point, to_point);
+    let dir = to_point / sqrt(distance_square);
+
+    var normal: vec3<f32>;
+    if (kind == 0u) {
+        let sphere = sphere_list.data[index];
+        let to_center = sphere.position - origin;
+        let center_distance_square = dot(to_center, to_center);
+        let radius_square = sphere.radius * sphere.radius;
+        if (center_distance_square > radius_square) {
+            // uniform over the cone, 1 - cos_max written to keep small
+            // spheres far away from rounding to zero
+            let cos_max = sqrt(1.0 - radius_square / center_distance_square);
+            return select_per_area * 2.0 * center_distance_square * (1.0 + cos_max);
+        }
+        normal = normalize(point - sphere.position).xyz;
+    } else if (kind == 2u) {
+        let triangle = triangle_list.data[index];
+        let object_to_world = instance_list.data[instance_index].object_to_world;
+        let v0 = object_to_world * vertex_list.data[triangle.indices.x].position;
+        let v1 = object_to_world * vertex_list.data[triangle.indices.y].position;
+        let v2 = object_to_world * vertex_list.data[triangle.indices.z].position;
+        normal = normalize(cross((v1 - v0).xyz, (v2 - v0).xyz));
+    } else {
+        normal = primitive_panel(primitive).normal.xyz;
+    }
+    let cos_light = abs(dot(dir.xyz, normal));
+    return select_per_area * distance_square / max(cos_light, 0.000001);
+}
 
-        pdf_mul = y_width * z_width;
-        sample_point = vec4<f32>(panel_point0.x, y_sample, z_sample, 1.0);
+// source: Veach, "Robust Monte Carlo Methods for Light Transport Simulation"
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if (a + b <= 0.0) {
+        return 0.0;
     }
-    if (abs(panel_normal.y) > 0.0) {
-        let x_width = panel_point1.x - panel_point0.x;
-        let z_width = panel_point1.z - panel_point0.z;
+    return a / (a + b);
+}
 
-        let rand2_res = rand_float_2_generate(state);
-        state = rand2_res.rng_state;
-        let x_sample = rand2_res.number0 * x_width + panel_point0.x;
-        let z_sample = rand2_res.number1 * z_width + panel_point0.z;
+// Equirectangular coordinates of a direction, v = 0 straight up and the
+// middle column along -z before rotation.
+fn environment_uv(dir: vec3<f32>) -> vec2<f32> {
+    let phi = atan2(dir.x, -dir.z) - config_data.environment_rotation;
+    let u = fract(0.5 + phi / (2.0 * 3.141592653));
+    let v = acos(clamp(dir.y, -1.0, 1.0)) / 3.141592653;
+    return vec2<f32>(u, v);
+}
 
-        pdf_mul = x_width * z_width;
-        sample_point = vec4<f32>(x_sample, panel_point0.y, z_sample, 1.0);
+fn environment_dir(uv: vec2<f32>) -> vec3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * 3.141592653 + config_data.environment_rotation;
+    let theta = uv.y * 3.141592653;
+    return vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+fn environment_texel(uv: vec2<f32>) -> vec2<i32> {
+    let size = textureDimensions(environment_texture);
+    let x = min(i32(uv.x * f32(size.x)), size.x - 1);
+    let y = min(i32(uv.y * f32(size.y)), size.y - 1);
+    return vec2<i32>(x, y);
+}
+
+fn environment_radiance(dir: vec3<f32>) -> vec3<f32> {
+    if (config_data.environment == 0u) {
+        return vec3<f32>(0.5, 0.5, 0.5);
     }
-    if (abs(panel_normal.z) > 0.0) {
-        let y_width = panel_point1.y - panel_point0.y;
-        let x_width = panel_point1.x - panel_point0.x;
+    let texel = textureLoad(environment_texture, environment_texel(environment_uv(dir)), 0);
+    return texel.xyz * config_data.environment_intensity;
+}
 
-        let rand2_res = rand_float_2_generate(state);
-        state = rand2_res.rng_state;
-        let y_sample = rand2_res.number0 * y_width + panel_point0.y;
-        let x_sample = rand2_res.number1 * x_width + panel_point0.z;
+// Solid angle density of `sample_environment` picking `dir`.
+fn environment_pdf(dir: vec3<f32>) -> f32 {
+    let integral = environment_cdf.data[0];
+    if (config_data.environment == 0u || integral <= 0.0) {
+        return 0.0;
+    }
+    let uv = environment_uv(dir);
+    let sin_theta = sin(uv.y * 3.141592653);
+    if (sin_theta <= 0.0) {
+        return 0.0;
+    }
+    // the probability of the cell, spread evenly over it
+    let width = u32(environment_cdf.data[1]);
+    let height = u32(environment_cdf.data[2]);
+    let x = min(u32(uv.x * f32(width)), width - 1u);
+    let y = min(u32(uv.y * f32(height)), height - 1u);
+    let row_offset = ENVIRONMENT_CDF_MARGINAL + height + 1u + y * (width + 1u);
+    let row_probability = environment_cdf.data[ENVIRONMENT_CDF_MARGINAL + y + 1u] - environment_cdf.data[ENVIRONMENT_CDF_MARGINAL + y];
+    let column_probability = environment_cdf.data[row_offset + x + 1u] - environment_cdf.data[row_offset + x];
+    return row_probability * column_probability * f32(width * height) / (2.0 * 3.141592653 * 3.141592653 * sin_theta);
+}
 
-        pdf_mul = y_width * x_width;
-        sample_point = vec4<f32>(x_sample, y_sample, panel_point0.z, 1.0);
+// Largest index into the CDF at `offset` whose value is at most `value`,
+// leaving out the final 1.
+fn find_interval(offset: u32, count: u32, value: f32) -> u32 {
+    var low: u32 = 0u;
+    var high: u32 = count - 1u;
+    loop {
+        if (low + 1u >= high) {
+            break;
+        }
+        let middle = (low + high) / 2u;
+        if (environment_cdf.data[offset + middle] <= value) {
+            low = middle;
+        } else {
+            high = middle;
+        }
     }
-    res.pdf_mul = pdf_mul;
-    res.sample_point = sample_point;
-    res.rng_state = state;
-    return res;
+    return low;
 }
 
-struct PointIntersectData {
-    ray_in_direction: vec4<f32>;
-    point: vec4<f32>;
-    point_normal: vec4<f32>;
-    point_albedo: vec3<f32>;
-    pdf_mul: f32;
-    light_sample_point: vec3<f32>;
-    point_material: i32;
+struct EnvironmentSample {
+    dir: vec4<f32>;
+    pdf: f32;
+    rng_state: u32;
 };
 
-struct HitInfoArrayData {
-    data: [[stride(80)]] array<PointIntersectData, 200>;
+// Picks a direction in proportion to the luminance of the map, row first
+// and then the column within it.
+fn sample_environment(rng_state: u32) -> EnvironmentSample {
+    let rand2_res = rand_float_2_generate(rng_state);
+    let width = u32(environment_cdf.data[1]);
+    let height = u32(environment_cdf.data[2]);
+
+    let marginal_offset = ENVIRONMENT_CDF_MARGINAL;
+    let y = find_interval(marginal_offset, height + 1u, rand2_res.number0);
+    let y_low = environment_cdf.data[marginal_offset + y];
+    let y_high = environment_cdf.data[marginal_offset + y + 1u];
+    let dv = (rand2_res.number0 - y_low) / max(y_high - y_low, 0.000001);
+
+    let row_offset = marginal_offset + height + 1u + y * (width + 1u);
+    let x = find_interval(row_offset, width + 1u, rand2_res.number1);
+    let x_low = environment_cdf.data[row_offset + x];
+    let x_high = environment_cdf.data[row_offset + x + 1u];
+    let du = (rand2_res.number1 - x_low) / max(x_high - x_low, 0.000001);
+
+    let uv = vec2<f32>((f32(x) + clamp(du, 0.0, 0.999)) / f32(width), (f32(y) + clamp(dv, 0.0, 0.999)) / f32(height));
+    var res: EnvironmentSample;
+    res.dir = vec4<f32>(environment_dir(uv), 0.0);
+    res.pdf = environment_pdf(res.dir.xyz);
+    res.rng_state = rand2_res.rng_state;
+    return res;
+}
+
+struct LightShadingData {
+    sample_point: vec4<f32>;
     rng_state: u32;
 };
 
-fn generate_hit_info_array(ray_in_origin: vec4<f32>, ray_in_direction: vec4<f32>, rng_state: u32) -> HitInfoArrayData {
-    var state: u32 = rng_state;
-    var res: HitInfoArrayData;
+// A point uniformly distributed over the panel.
+fn light_get_direct_shading_data(panel: Panel, rng_state: u32) -> LightShadingData {
+    let rand2_res = rand_float_2_generate(rng_state);
+    var res: LightShadingData;
+    res.sample_point = panel.origin + rand2_res.number0 * panel.edge_u + rand2_res.number1 * panel.edge_v;
+    res.rng_state = rand2_res.rng_state;
+    return res;
+}
 
-    var array_index: u32 = 0u;
-    var ray_origin: vec4<f32> = ray_in_origin;
-    var ray_direction: vec4<f32> = ray_in_direction;
-    var missed: bool = false;
-    var light_indicator: bool = false;
+struct EmitterSample {
+    dir: vec4<f32>;
+    distance_square: f32;
+    emission: vec3<f32>;
+    // solid angle density, zero when the emitter faces away
+    pdf: f32;
+    rng_state: u32;
+};
+
+fn find_emitter(value: f32) -> u32 {
+    var low: u32 = 0u;
+    var high: u32 = config_data.emitter_count - 1u;
     loop {
-        if (array_index >= 200u) {
+        if (low >= high) {
             break;
         }
-        if (missed) {
-            var intersect_data: PointIntersectData;
-            intersect_data.point_material = -15;
-            res.data[array_index] = intersect_data;
-        }
-        if (light_indicator) {
-            var intersect_data: PointIntersectData;
-            intersect_data.point_material = 205;
+        let middle = (low + high) / 2u;
+        if (emitter_list.data[middle].cdf > value) {
+            high = middle;
+        } else {
+            low = middle + 1u;
         }
+    }
+    return low;
+}
 
-        let hit_info = ray_intersect(ray_origin, ray_direction);
-        let light_panel = light_list.data[0];
-        let sample_data = light_get_direct_shading_data(light_panel.point0, light_panel.point1, light_panel.normal, state);
-        state = sample_data.rng_state;
-
-        var intersect_data: PointIntersectData;
-        intersect_data.ray_in_direction = ray_direction;
-        intersect_data.point = hit_info.hit_point;
-        intersect_data.point_normal = hit_info.hit_normal;
-        intersect_data.point_albedo = hit_info.albedo;
-        intersect_data.point_material = hit_info.hit_material;
-        intersect_data.pdf_mul = sample_data.pdf_mul;
-        intersect_data.light_sample_point = vec3<f32>(sample_data.sample_point.x, sample_data.sample_point.y, sample_data.sample_point.z);
-
-        if (hit_info.hit_material < 0) {
-            missed = true;
-        }
-        if (hit_info.hit_material > 100) {
-            light_indicator = true;
+// Picks an emitter by power and a point on it seen from `point`: spheres
+// over the cone they cover, or their whole surface from inside, triangles
+// and panels uniformly over their area.
+fn sample_emitter(point: vec4<f32>, rng_state: u32) -> EmitterSample {
+    let rand_res = rand_float_generate(rng_state);
+    let rand2_res = rand_float_2_generate(rand_res.rng_state);
+    let emitter = emitter_list.data[find_emitter(rand_res.number)];
+    let primitive = emitter.primitive;
+    let kind = primitive >> 30u;
+    let index = primitive & 1073741823u;
+
+    var res: EmitterSample;
+    res.rng_state = rand2_res.rng_state;
+    var sample_point: vec4<f32>;
+    var material: u32;
+    var front: bool = true;
+    if (kind == 0u) {
+        let sphere = sphere_list.data[index];
+        material = sphere.material;
+        let to_center = sphere.position - point;
+        let center_distance_square = dot(to_center, to_center);
+        let radius_square = sphere.radius * sphere.radius;
+        let phi = 2.0 * 3.141592653 * rand2_res.number1;
+        if (center_distance_square > radius_square) {
+            let sin_max_square = radius_square / center_distance_square;
+            let cos_max = sqrt(1.0 - sin_max_square);
+            let cos_theta = 1.0 - rand2_res.number0 * sin_max_square / (1.0 + cos_max);
+            let sin_theta_square = max(1.0 - cos_theta * cos_theta, 0.0);
+            let sin_theta = sqrt(sin_theta_square);
+            let center_distance = sqrt(center_distance_square);
+            let frame = tangent_frame(to_center.xyz / center_distance);
+            let dir = frame * vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+            let distance = center_distance * cos_theta - sqrt(max(radius_square - center_distance_square * sin_theta_square, 0.0));
+            sample_point = point + distance * vec4<f32>(dir, 0.0);
+        } else {
+            let z = 1.0 - 2.0 * rand2_res.number0;
+            let r = sqrt(max(1.0 - z * z, 0.0));
+            sample_point = sphere.position + sphere.radius * vec4<f32>(r * cos(phi), r * sin(phi), z, 0.0);
         }
+    } else if (kind == 2u) {
+        let triangle = triangle_list.data[index];
+        material = triangle.material;
+        let object_to_world = instance_list.data[emitter.instance].object_to_world;
+        let v0 = object_to_world * vertex_list.data[triangle.indices.x].position;
+        let v1 = object_to_world * vertex_list.data[triangle.indices.y].position;
+        let v2 = object_to_world * vertex_list.data[triangle.indices.z].position;
+        let su = sqrt(rand2_res.number0);
+        sample_point = (1.0 - su) * v0 + su * (1.0 - rand2_res.number1) * v1 + su * rand2_res.number1 * v2;
+    } else {
+        let panel = primitive_panel(primitive);
+        material = panel.material;
+        let sample_data = light_get_direct_shading_data(panel, rand_res.rng_state);
+        sample_point = sample_data.sample_point;
+        res.rng_state = sample_data.rng_state;
+        // panels only shine on their front
+        front = dot(sample_point - point, panel.normal) < 0.0;
+    }
 
-        ray_origin = hit_info.hit_point;
-        let scatter_data = generate_scatter_ray_dir(hit_info, state);
-        ray_direction = scatter_data.dir;
-        intersect_data.point_normal[3] = scatter_data.pdf;
-        state = scatter_data.rng_state;
+    let to_sample = sample_point - point;
+    res.distance_square = dot(to_sample, to_sample);
+    res.dir = normalize(to_sample);
+    res.emission = material_list.data[material].color;
+    res.pdf = 0.0;
+    if (front) {
+        res.pdf = emitter_pdf(primitive, emitter.instance, point, sample_point, res.emission);
+    }
+    return res;
+}
 
-        res.data[array_index] = intersect_data;
-        continuing {
-            array_index = array_index + 1u;
+// Light reaching `point` from a point, spot or directional light, already
+// scaled by the brdf; zero when it is shadowed.
+fn delta_light_shading(light: DeltaLight, material: Material, point: vec4<f32>, normal: vec4<f32>, wo: vec3<f32>) -> vec3<f32> {
+    var light_dir: vec4<f32>;
+    var distance_square: f32;
+    var incident: vec3<f32>;
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        light_dir = -light.direction;
+        distance_square = 1000000000.0 * 1000000000.0;
+        incident = light.intensity;
+    } else {
+        let to_light = light.position - point;
+        distance_square = dot(to_light, to_light);
+        light_dir = normalize(to_light);
+        incident = light.intensity / distance_square;
+        if (light.kind == LIGHT_SPOT) {
+            // smoothstep from the outer to the inner cone
+            let cone = clamp((dot(-light_dir, light.direction) - light.cos_outer) / max(light.cos_inner - light.cos_outer, 0.000001), 0.0, 1.0);
+            incident = incident * cone * cone * (3.0 - 2.0 * cone);
         }
     }
-    res.rng_state = state;
-    return res;
+
+    let brdf_cos = eval_brdf_cos(material, wo, light_dir.xyz, normal.xyz);
+    if (max(max(brdf_cos.x, brdf_cos.y), brdf_cos.z) <= 0.0 || max(max(incident.x, incident.y), incident.z) <= 0.0) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    let shadow_hit = ray_intersect_without_light(point, light_dir);
+    if (shadow_hit.t * shadow_hit.t < distance_square * 0.999) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    return brdf_cos * incident;
 }
 
-fn shade_point_array(point_array: HitInfoArrayData) -> vec3<f32> {
-    var info_array: array<PointIntersectData, 200> = point_array.data;
-    var shade_color: vec3<f32>;
-    var index: i32 = 199;
+struct PathResult {
+    radiance: vec3<f32>;
+    rng_state: u32;
+};
+
+// Follows one camera ray, adding light sampled at every bounce and scaling
+// it by the throughput of the path so far. Paths end on a miss, on an
+// emitter, at `max_depth` bounces or by Russian roulette.
+fn trace_path(ray_in_origin: vec4<f32>, ray_in_direction: vec4<f32>, rng_state: u32) -> PathResult {
+    var state: u32 = rng_state;
+    var radiance: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    var throughput: vec3<f32> = vec3<f32>(1.0, 1.0, 1.0);
+    var ray_origin: vec4<f32> = ray_in_origin;
+    var ray_direction: vec4<f32> = ray_in_direction;
+    // emitters reached by scattering share their light with light sampling,
+    // except after bounces light sampling cannot reach
+    var specular_bounce: bool = true;
+    var scatter_pdf: f32 = 0.0;
+    var depth: u32 = 0u;
     loop {
-        if (index < 0) {
+        if (depth >= config_data.max_depth) {
             break;
         }
 
-        let point_info = info_array[index];
-        if (point_info.point_material < -10 || point_info.point_material > 200) {
-            continue;
+        let hit_info = ray_intersect(ray_origin, ray_direction);
+        if (hit_info.hit_material < 0) {
+            var mis_weight: f32 = 1.0;
+            if (!specular_bounce) {
+                mis_weight = power_heuristic(scatter_pdf, environment_pdf(ray_direction.xyz));
+            }
+            radiance = radiance + throughput * environment_radiance(ray_direction.xyz) * mis_weight;
+            break;
         }
-        if (point_info.point_material < 0) {
-            shade_color = vec3<f32>(0.5, 0.5, 0.5);
-            continue;
+        let material = material_list.data[hit_info.hit_material];
+        if (material.kind == MATERIAL_EMISSIVE) {
+            var mis_weight: f32 = 1.0;
+            if (!specular_bounce) {
+                let pdf = emitter_pdf(hit_info.hit_primitive, hit_info.hit_instance, ray_origin, hit_info.hit_point, material.color);
+                mis_weight = power_heuristic(scatter_pdf, pdf);
+            }
+            radiance = radiance + throughput * material.color * mis_weight;
+            break;
         }
-        if (point_info.point_material > 100) {
-            shade_color = point_info.point_albedo;
-            continue;
+
+        // shading expects the normal on the side the ray came from
+        let front_face = dot(ray_direction, hit_info.hit_normal) < 0.0;
+        var point_normal: vec4<f32> = hit_info.hit_normal;
+        if (!front_face) {
+            point_normal = -point_normal;
         }
-        let ray_in_dir = point_info.ray_in_direction;
-        let point = point_info.point;
-        let point_normal = vec4<f32>(point_info.point_normal.x, point_info.point_normal.y, point_info.point_normal.z, 0.0);
-        let point_albedo = point_info.point_albedo;
-        let point_pdf_mul = point_info.pdf_mul;
-        let point_pdf = point_info.point_normal[3];
-        let point_sample_point = vec4<f32>(point_info.light_sample_point, 1.0);
-
-        // fresnel factor
-        let f0: f32 = 0.45;
-        let fresnel_factor = f0 + (1.0 - f0) * pow(abs(dot(ray_in_dir, point_normal)), 5.0);
-
-        // if (index == 0) {
-        //     shade_color = point_albedo * fresnel_factor;
-        // } else {
-        //     continue;
-        // }
-
-        // direct shading
-        var direct_shade_res: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
-        var light_index: i32 = 0;
-        let light_count: i32 = i32(config_data.light_count);
-        loop {
-            if (light_index >= light_count) {
-                break;
+        let point = hit_info.hit_point;
+        let wo = -ray_direction.xyz;
+
+        // direct shading from a point on one emitter picked by power,
+        // weighted against scattering into the same emitter
+        if (config_data.emitter_count > 0u && (material.kind == MATERIAL_LAMBERTIAN || material.kind == MATERIAL_PBR)) {
+            let emitter_sample = sample_emitter(point, state);
+            state = emitter_sample.rng_state;
+            let light_dir = emitter_sample.dir;
+            if (emitter_sample.pdf > 0.0) {
+                // emissive surfaces other than lights block the shadow ray
+                // themselves, right at the sampled point
+                let shadow_hit = ray_intersect_without_light(point, light_dir);
+                if (shadow_hit.t * shadow_hit.t >= emitter_sample.distance_square * 0.999) {
+                    let brdf_cos = eval_brdf_cos(material, wo, light_dir.xyz, point_normal.xyz);
+                    let pdf = emitter_sample.pdf;
+                    let mis_weight = power_heuristic(pdf, brdf_pdf(material, wo, light_dir.xyz, point_normal.xyz));
+                    radiance = radiance + throughput * brdf_cos * emitter_sample.emission * mis_weight / pdf;
+                }
             }
+        }
 
-            let light_panel = light_list.data[light_index];
-            if (dot(point_normal, light_panel.normal) > 0.0) {
-                continue;
-            }
-            
-            let ptsp = point_sample_point - point;
-            let length_square = dot(ptsp, ptsp);
-            let temp_dir = normalize(ptsp);
-            let temp_hit_info = ray_intersect_without_light(point, temp_dir);
-            let temp_pp = temp_hit_info.hit_point - point;
-            if (dot(temp_pp, temp_pp) < length_square) {
-                continue;
+        // direct shading from the environment, weighted against scattering
+        // out of the scene
+        if (config_data.environment != 0u && environment_cdf.data[0] > 0.0 && (material.kind == MATERIAL_LAMBERTIAN || material.kind == MATERIAL_PBR)) {
+            let environment_sample = sample_environment(state);
+            state = environment_sample.rng_state;
+            let light_dir = environment_sample.dir;
+            let brdf_cos = eval_brdf_cos(material, wo, light_dir.xyz, point_normal.xyz);
+            if (environment_sample.pdf > 0.0 && max(max(brdf_cos.x, brdf_cos.y), brdf_cos.z) > 0.0) {
+                let shadow_hit = ray_intersect(point, light_dir);
+                if (shadow_hit.hit_material < 0) {
+                    let mis_weight = power_heuristic(environment_sample.pdf, brdf_pdf(material, wo, light_dir.xyz, point_normal.xyz));
+                    radiance = radiance + throughput * brdf_cos * environment_radiance(light_dir.xyz) * mis_weight / environment_sample.pdf;
+                }
             }
+        }
 
-            let cos_theta = abs(dot(temp_dir, point_normal));
-            let cos_theta_prime = abs(dot(temp_dir, light_panel.normal));
-            direct_shade_res = direct_shade_res + point_albedo * light_panel.color * cos_theta * cos_theta_prime * point_pdf_mul * fresnel_factor / length_square;
-            // direct_shade_res = direct_shade_res + light_panel.color * cos_theta * cos_theta_prime * point_pdf_mul * fresnel_factor / length_square;
-            // direct_shade_res = direct_shade_res + point_albedo * light_panel.color * 0.00001 * cos_theta * cos_theta_prime;
+        // delta lights cannot be hit by scattering, so they are all sampled
+        if (material.kind == MATERIAL_LAMBERTIAN || material.kind == MATERIAL_PBR) {
+            var delta_light_index: u32 = 0u;
+            loop {
+                if (delta_light_index >= config_data.delta_light_count) {
+                    break;
+                }
 
-            continuing {
-                light_index = light_index + 1;
+                let light = delta_light_list.data[delta_light_index];
+                radiance = radiance + throughput * delta_light_shading(light, material, point, point_normal, wo);
+
+                continuing {
+                    delta_light_index = delta_light_index + 1u;
+                }
             }
         }
 
-        // indirect shading
-        var indirect_shade_res: vec3<f32>;
-        if (index == 199) {
-            indirect_shade_res = vec3<f32>(0.0, 0.0, 0.0);
-        } else {
-            indirect_shade_res = shade_color * point_albedo * dot(point_normal, info_array[index + 1].ray_in_direction) * fresnel_factor * point_pdf;
-        }
+        let scatter_data = generate_scatter_ray_dir(material, ray_direction, point_normal, front_face, state);
+        state = scatter_data.rng_state;
+        throughput = throughput * scatter_data.weight;
+        specular_bounce = scatter_data.pdf <= 0.0;
+        scatter_pdf = scatter_data.pdf;
+        ray_origin = point;
+        ray_direction = scatter_data.dir;
 
-        shade_color = direct_shade_res + indirect_shade_res;
+        // Russian roulette: end dim paths early, boosting the ones that
+        // survive so the expected value stays the same
+        let max_throughput = max(max(throughput.x, throughput.y), throughput.z);
+        if (max_throughput <= 0.0) {
+            break;
+        }
+        if (depth + 1u >= config_data.russian_roulette_depth) {
+            let survival = clamp(max_throughput, 0.05, 1.0);
+            let rand_res = rand_float_generate(state);
+            state = rand_res.rng_state;
+            if (rand_res.number >= survival) {
+                break;
+            }
+            throughput = throughput / survival;
+        }
 
-        // if (index <= 0u) {
-        //     break;
-        // }
         continuing {
-            index = index - 1;
+            depth = depth + 1u;
         }
     }
 
-    return shade_color;
+    var res: PathResult;
+    res.radiance = radiance;
+    res.rng_state = state;
+    return res;
 }
 
-// fn generate_spp_array(screen_pos: vec2<f32>) -> array<vec2<f32>, 20> {
-//     var res: array<vec2<f32>, 20>;
-//     var index: i32 = 0;
-//     let total_sample_num: i32 = i32(config_data.spp);
-//     loop {
-//         if (index >= total_sample_num) {
-//             break;
-//         } 
-
-//         let rand_col: f32 = rand_float_generate();
-//         let rand_row: f32 = rand_float_generate();
-//         let x: f32 = screen_pos.x + rand_col - f32(config_data.window_width) / 2.0;
-//         let y: f32 = f32(config_data.window_height) / 2.0 - screen_pos.y + rand_row;
+// spheres, panels, lights, then mesh instances in one range
+fn object_index(hit_info: HitInfo) -> u32 {
+    let kind = hit_info.hit_primitive >> 30u;
+    let index = hit_info.hit_primitive & 0x3fffffffu;
+    if (kind == 0u) {
+        return index;
+    } else if (kind == 1u) {
+        return config_data.sphere_count + index;
+    } else if (kind == 3u) {
+        return config_data.sphere_count + config_data.panel_count + index;
+    }
+    return config_data.sphere_count + config_data.panel_count + config_data.light_count + hit_info.hit_instance;
+}
 
-//         res[index] = vec2<f32>(x, y);
+fn write_aovs(pixel_index: u32, ray_origin: vec4<f32>, ray_direction: vec4<f32>, forward: vec4<f32>) {
+    let base = pixel_index * 3u;
+    let hit_info = ray_intersect(ray_origin, ray_direction);
+    if (hit_info.hit_material < 0) {
+        aov_list.data[base] = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+        aov_list.data[base + 1u] = vec4<f32>(0.0, 0.0, 0.0, -1.0);
+        aov_list.data[base + 2u] = vec4<f32>(0.0, 0.0, 0.0, -1.0);
+        return;
+    }
 
-//         continuing {
-//             index = index + 1;
-//         }
-//     }
+    let material = material_list.data[hit_info.hit_material];
+    var normal: vec3<f32> = hit_info.hit_normal.xyz;
+    if (dot(ray_direction.xyz, normal) > 0.0) {
+        normal = -normal;
+    }
+    let depth = dot(hit_info.hit_point.xyz - ray_origin.xyz, forward.xyz);
+    // emission stands in for the albedo of lights, clamped like a color
+    let albedo = min(material.color, vec3<f32>(1.0, 1.0, 1.0));
+    aov_list.data[base] = vec4<f32>(albedo, depth);
+    aov_list.data[base + 1u] = vec4<f32>(normal, f32(hit_info.hit_material));
+    aov_list.data[base + 2u] = vec4<f32>(hit_info.hit_point.xyz, f32(object_index(hit_info)));
+}
 
-//     return res;
-// }
+// workgroups along x of a dispatch, larger images take more rows of them,
+// as `tracer::DISPATCH_ROW_GROUPS`
+let DISPATCH_ROW_GROUPS: u32 = 32768u;
 
 [[stage(compute), workgroup_size(64)]]
 fn main([[builtin(global_invocation_id)]] gi_id: vec3<u32>) {
     var out_color: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
-    let entity_index = i32(gi_id.x);
-    var rng_state: u32 = gi_id.x;
+    let pixel_index = gi_id.y * DISPATCH_ROW_GROUPS * 64u + gi_id.x;
+    let entity_index = i32(pixel_index);
+    if (pixel_index >= config_data.window_width * config_data.window_height) {
+        return;
+    }
+    var rng_state: u32 = pcg_hash(pixel_index ^ pcg_hash(config_data.seed + config_data.frame_index));
 
-    let camera_pos = vec4<f32>(300.0, 300.0, 800.0, 1.0);
     let window_width = f32(config_data.window_width);
     let window_height = f32(config_data.window_height);
+    let half_height = tan(camera.vfov * 3.141592653 / 360.0);
+    let half_width = half_height * camera.aspect;
+    let forward = normalize(camera.target - camera.eye);
+    let right = vec4<f32>(normalize(cross(forward.xyz, camera.up)), 0.0);
+    let up = vec4<f32>(cross(right.xyz, forward.xyz), 0.0);
 
     var n: i32 = 0;
     let spp: i32 = i32(config_data.spp);
-    let screen_pos = input_list.data[entity_index].col_row;
-    // loop {
-    //     if (n >= 10) {
-    //         out_color = out_color / f32(n);
-    //         break;
-    //     }
-
-        var res_albedo: vec3<f32>;
-        var ray_origin: vec4<f32> = camera_pos;
+    // pixels are numbered row by row from the top left
+    let screen_pos = vec2<f32>(
+        f32(pixel_index % config_data.window_width),
+        f32(pixel_index / config_data.window_width),
+    );
+
+    // first-hit AOVs come from the pixel center, once per accumulation
+    if (config_data.aov != 0u && config_data.frame_index == 0u) {
+        let x: f32 = (screen_pos.x + 0.5) / window_width * 2.0 - 1.0;
+        let y: f32 = 1.0 - (screen_pos.y + 0.5) / window_height * 2.0;
+        let center_direction = normalize(forward + x * half_width * right + y * half_height * up);
+        write_aovs(pixel_index, camera.eye, center_direction, forward);
+    }
+
+    loop {
+        if (n >= spp) {
+            out_color = out_color / f32(n);
+            break;
+        }
+
+        rng_state = sampler_begin(pixel_index, config_data.frame_index * config_data.spp + u32(n), rng_state);
+        var ray_origin: vec4<f32> = camera.eye;
         let rand2_gen_res = rand_float_2_generate(rng_state);
         rng_state = rand2_gen_res.rng_state;
         let rand_col: f32 = rand2_gen_res.number0;
         let rand_row: f32 = rand2_gen_res.number1;
-        let x: f32 = screen_pos.x + rand_col - window_width / 2.0;
-        let y: f32 = window_height / 2.0 - screen_pos.y + rand_row;
-        var ray_direction: vec4<f32> = normalize(vec4<f32>(x, y, 0.0, 1.0) - vec4<f32>(0.0, 0.0, 800.0, 1.0));
-        let hit_array_data = generate_hit_info_array(ray_origin, ray_direction, rng_state);
-        rng_state = hit_array_data.rng_state;
-        // res_albedo = shade_point_array(hit_array_data);
-        out_color = shade_point_array(hit_array_data);
-        
-    //     continuing {
-    //         n = n + 1;
-    //         out_color = out_color + res_albedo;
-    //     }
-    // }
-    
-    let r: f32 = clamp(sqrt(out_color.x), 0.0, 1.0) * 255.0;
-    let g: f32 = clamp(sqrt(out_color.y), 0.0, 1.0) * 255.0;
-    let b: f32 = clamp(sqrt(out_color.z), 0.0, 1.0) * 255.0;
-    let res_color_temp: vec4<u32> = vec4<u32>(u32(b), u32(g), u32(r), 1u);
-    let res_color: u32 = res_color_temp[0] | res_color_temp[1] << 8u | res_color_temp[2] << 16u | res_color_temp[3] << 24u;
-    // let res_color: u32 = res_color_temp[3] | res_color_temp[2] << 8u | res_color_temp[1] << 16u | res_color_temp[0] << 24u;
-    output_list.data[entity_index] = res_color;
+        let x: f32 = (screen_pos.x + rand_col) / window_width * 2.0 - 1.0;
+        let y: f32 = 1.0 - (screen_pos.y + rand_row) / window_height * 2.0;
+        var ray_direction: vec4<f32> = normalize(forward + x * half_width * right + y * half_height * up);
+        let path_result = trace_path(ray_origin, ray_direction, rng_state);
+        rng_state = path_result.rng_state;
+
+        continuing {
+            n = n + 1;
+            out_color = out_color + path_result.radiance;
+        }
+    }
+
+    var accumulated: vec4<f32> = vec4<f32>(out_color, 1.0);
+    if (config_data.frame_index > 0u) {
+        accumulated = accumulated + accumulation_list.data[entity_index];
+    }
+    accumulation_list.data[entity_index] = accumulated;
+    out_color = accumulated.xyz / f32(config_data.frame_index + 1u);
+
+    // linear and unclamped, the tonemap pass turns it into display colors
+    radiance_list.data[entity_index] = vec4<f32>(out_color, 1.0);
 }
\ No newline at end of file
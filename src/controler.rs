@@ -1,15 +1,10 @@
 use wgpu::{util::DeviceExt, vertex_attr_array};
 
 use crate::{
-    entity::{Panel, Sphere},
-    settings::{
-        SAMPLES_PER_PIXEL, TEXTURE_HEIGHT, TEXTURE_WIDTH, WINDOW_HEIGHT, WINDOW_TOTAL_PIXEL,
-        WINDOW_WIDHT,
-    },
-    systems::generator::{
-        generate_clip_rect, generate_input_data, generate_lights_scene, generate_panel_scene,
-        generate_sphere_scene,
-    },
+    entity::Camera,
+    settings::{Denoise, ScaleFilter, Settings, Tonemap, MAX_RENDER_SCALE, MIN_RENDER_SCALE},
+    systems::{aov::View, generator::generate_clip_rect, scene::Scene},
+    tracer::{check_environment_size, check_trace_size, required_limits, Tracer},
 };
 
 pub struct Controler {
@@ -17,23 +12,32 @@ pub struct Controler {
     queue: wgpu::Queue,
     surface: wgpu::Surface,
     surface_format: wgpu::TextureFormat,
+    surface_config: wgpu::SurfaceConfiguration,
+    texture_sampler: wgpu::Sampler,
+    blit_buffer: wgpu::Buffer,
+    cell_render_bind_group_layout: wgpu::BindGroupLayout,
     cell_render_bind_group: wgpu::BindGroup,
     cell_render_buffer: wgpu::Buffer,
     cell_render_pipeline: wgpu::RenderPipeline,
-    // entity_buffers: Vec<wgpu::Buffer>,
-    input_buffer: wgpu::Buffer,
-    result_buffer: wgpu::Buffer,
     source_texture: wgpu::Texture,
-    compute_pipeline: wgpu::ComputePipeline,
-    compute_bindgroup0: wgpu::BindGroup,
-    compute_bindgroup1: wgpu::BindGroup,
-    work_group_count: u32,
+    tracer: Tracer,
     clip_rect: (u32, u32, u32, u32),
+    texture_size: (u32, u32),
+    settings: Settings,
 }
 
 impl Controler {
-    pub async fn new(window: &winit::window::Window) -> Self {
-        let clip_rect = generate_clip_rect();
+    /// Fails with a message ready to print when there is no adapter, it
+    /// lacks storage buffers or the trace or environment map is too large
+    /// for the device.
+    pub async fn new(
+        window: &winit::window::Window,
+        settings: &Settings,
+        scene: &Scene,
+    ) -> Result<Self, String> {
+        let window_size = window.inner_size();
+        let texture_size = settings.trace_size((window_size.width, window_size.height));
+        let clip_rect = generate_clip_rect((window_size.width, window_size.height), texture_size);
         let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
         let surface = unsafe { instance.create_surface(window) };
         let adapter = instance
@@ -43,60 +47,50 @@ impl Controler {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .ok_or_else(|| "No suitable adapter found".to_string())?;
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
                     features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
+                    limits: required_limits(&adapter)?,
                 },
                 None,
             )
             .await
-            .unwrap();
-        let surface_format = surface.get_preferred_format(&adapter).unwrap();
+            .map_err(|e| format!("Failed to create device: {}", e))?;
+        let surface_format = surface
+            .get_preferred_format(&adapter)
+            .ok_or_else(|| "The adapter cannot present to the window".to_string())?;
+        log::debug!("Surface format {:?}", surface_format);
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
-            width: WINDOW_WIDHT,
-            height: WINDOW_HEIGHT,
+            width: window_size.width,
+            height: window_size.height,
             present_mode: wgpu::PresentMode::Immediate,
         };
         surface.configure(&device, &surface_config);
-
-        let compute_shader_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-            label: Some("Compute-Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("compute-shader.wgsl").into()),
-        });
+        check_trace_size(
+            &device.limits(),
+            texture_size.0,
+            texture_size.1,
+            settings.aov_enabled(),
+        )?;
+        check_environment_size(&device.limits(), scene)?;
 
         let base_render_shader_module =
             device.create_shader_module(&wgpu::ShaderModuleDescriptor {
                 label: Some("Render-Shader"),
                 source: wgpu::ShaderSource::Wgsl(include_str!("base-render-shader.wgsl").into()),
             });
-        let source_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: TEXTURE_WIDTH,
-                height: TEXTURE_HEIGHT,
-                // width: WINDOW_WIDHT,
-                // height: WINDOW_HEIGHT,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: surface_format,
-            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
-        });
         let texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: None,
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             lod_min_clamp: 0.0,
             lod_max_clamp: 1.0,
@@ -105,6 +99,19 @@ impl Controler {
             border_color: None,
         });
 
+        // nearest sampling is done in the shader, the sampler always filters
+        let blit_buffer_data: [u32; 4] = [
+            (settings.scale_filter == ScaleFilter::Nearest) as u32,
+            0,
+            0,
+            0,
+        ];
+        let blit_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Blit-Buffer"),
+            contents: bytemuck::cast_slice(&blit_buffer_data),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
         let cell_buffer_data: [[[f32; 2]; 2]; 3] = [
             // One full-screen triangle
             // See: https://github.com/parasyte/pixels/issues/180
@@ -145,24 +152,26 @@ impl Controler {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: std::num::NonZeroU64::new(16),
+                        },
+                        count: None,
+                    },
                 ],
             });
-        let source_texture_view =
-            source_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let cell_render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Render-Bindgroup"),
-            layout: &cell_render_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&source_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture_sampler),
-                },
-            ],
-        });
+        let (source_texture, cell_render_bind_group) = create_source_texture(
+            &device,
+            surface_format,
+            &cell_render_bind_group_layout,
+            &texture_sampler,
+            &blit_buffer,
+            texture_size,
+        );
 
         let cell_render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -175,7 +184,7 @@ impl Controler {
             layout: Some(&cell_render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &base_render_shader_module,
-                entry_point: "base_main",
+                entry_point: "vs_main",
                 buffers: &[cell_render_buffer_layout],
             },
             primitive: wgpu::PrimitiveState::default(),
@@ -183,7 +192,7 @@ impl Controler {
             multisample: wgpu::MultisampleState::default(),
             fragment: Some(wgpu::FragmentState {
                 module: &base_render_shader_module,
-                entry_point: "base_main",
+                entry_point: "fs_main",
                 targets: &[wgpu::ColorTargetState {
                     format: surface_format,
                     blend: Some(wgpu::BlendState::REPLACE),
@@ -193,233 +202,164 @@ impl Controler {
             multiview: None,
         });
 
-        let sphere_buffer_data = generate_sphere_scene();
-        let panel_buffer_data = generate_panel_scene();
-        let light_buffer_data = generate_lights_scene();
-        let entity_buffers = vec![
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(&sphere_buffer_data),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC,
-            }),
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(&panel_buffer_data),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC,
-            }),
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(&light_buffer_data),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC,
-            }),
-        ];
-
-        let config_buffer_data = vec![
-            0u32,
-            WINDOW_WIDHT,
-            WINDOW_HEIGHT,
-            SAMPLES_PER_PIXEL as u32,
-            sphere_buffer_data.len() as u32,
-            panel_buffer_data.len() as u32,
-            light_buffer_data.len() as u32,
-            0u32,
-        ];
-        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&config_buffer_data),
-            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
-        });
-
-        let input_buffer_data = generate_input_data();
-        let input_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Input-Buffer"),
-            contents: bytemuck::cast_slice(&input_buffer_data),
-            usage: wgpu::BufferUsages::COPY_SRC
-                | wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::VERTEX,
-        });
-
-        let result_buffer_data = vec![0u8; (WINDOW_TOTAL_PIXEL * 4) as usize]; // bgra8usnormsRGB
-        let result_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Res-Buffer"),
-            contents: bytemuck::cast_slice(&result_buffer_data),
-            usage: wgpu::BufferUsages::COPY_DST
-                | wgpu::BufferUsages::COPY_SRC
-                | wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::VERTEX,
-        });
-
-        let compute_bindgroup0_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(
-                                std::num::NonZeroU64::new(WINDOW_TOTAL_PIXEL * 2 * 4).unwrap(),
-                            ),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(
-                                std::num::NonZeroU64::new(WINDOW_TOTAL_PIXEL * 4 * 1).unwrap(),
-                            ),
-                        },
-                        count: None,
-                    },
-                ],
-            });
-        let compute_bindgroup0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Compute-Bindgroup"),
-            layout: &compute_bindgroup0_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: input_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: result_buffer.as_entire_binding(),
-                },
-            ],
-        });
-        let compute_bindgroup1_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(
-                                std::num::NonZeroU64::new(
-                                    (sphere_buffer_data.len() * std::mem::size_of::<Sphere>())
-                                        as u64,
-                                )
-                                .unwrap(),
-                            ),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(
-                                std::num::NonZeroU64::new(
-                                    (panel_buffer_data.len() * std::mem::size_of::<Panel>()) as u64,
-                                )
-                                .unwrap(),
-                            ),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(
-                                std::num::NonZeroU64::new(
-                                    (light_buffer_data.len() * std::mem::size_of::<Panel>()) as u64,
-                                )
-                                .unwrap(),
-                            ),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(
-                                std::num::NonZeroU64::new(
-                                    (config_buffer_data.len() * std::mem::size_of::<u32>()) as u64,
-                                )
-                                .unwrap(),
-                            ),
-                        },
-                        count: None,
-                    },
-                ],
-            });
-        let compute_bindgroup1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Entity"),
-            layout: &compute_bindgroup1_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: entity_buffers[0].as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: entity_buffers[1].as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: entity_buffers[2].as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: config_buffer.as_entire_binding(),
-                },
-            ],
-        });
-
-        let compute_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[&compute_bindgroup0_layout, &compute_bindgroup1_layout],
-                push_constant_ranges: &[],
-            });
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: None,
-            layout: Some(&compute_pipeline_layout),
-            module: &compute_shader_module,
-            entry_point: "main",
-        });
-
-        let work_group_count = ((WINDOW_TOTAL_PIXEL as f32) / 64.0).ceil() as u32;
+        let tracer = Tracer::new(
+            &device,
+            &queue,
+            &Settings {
+                width: texture_size.0,
+                height: texture_size.1,
+                ..settings.clone()
+            },
+            scene,
+        );
 
-        Controler {
+        Ok(Controler {
             device,
             queue,
             surface,
             surface_format,
+            surface_config,
+            texture_sampler,
+            blit_buffer,
+            cell_render_bind_group_layout,
             cell_render_bind_group,
             cell_render_buffer,
             cell_render_pipeline,
-            // entity_buffers,
-            input_buffer,
-            result_buffer,
             source_texture,
-            compute_pipeline,
-            compute_bindgroup0,
-            compute_bindgroup1,
-            work_group_count,
+            tracer,
             clip_rect,
+            texture_size,
+            settings: settings.clone(),
+        })
+    }
+
+    /// Follows a new window size: reconfigures the surface and traces at
+    /// the render scale of the new size from scratch. Minimized windows
+    /// report a zero size and are skipped, and a trace too large for the
+    /// device keeps its old size, scaled to the window.
+    pub fn resize(&mut self, window_size: winit::dpi::PhysicalSize<u32>) {
+        if window_size.width == 0 || window_size.height == 0 {
+            return;
         }
+        self.surface_config.width = window_size.width;
+        self.surface_config.height = window_size.height;
+        self.surface.configure(&self.device, &self.surface_config);
+
+        let texture_size = self
+            .settings
+            .trace_size((window_size.width, window_size.height));
+        if let Err(message) = check_trace_size(
+            &self.device.limits(),
+            texture_size.0,
+            texture_size.1,
+            self.tracer.aov_enabled(),
+        ) {
+            eprintln!("{}", message);
+            self.clip_rect =
+                generate_clip_rect((window_size.width, window_size.height), self.texture_size);
+            return;
+        }
+        self.texture_size = texture_size;
+        let (source_texture, cell_render_bind_group) = create_source_texture(
+            &self.device,
+            self.surface_format,
+            &self.cell_render_bind_group_layout,
+            &self.texture_sampler,
+            &self.blit_buffer,
+            self.texture_size,
+        );
+        self.source_texture = source_texture;
+        self.cell_render_bind_group = cell_render_bind_group;
+        self.clip_rect =
+            generate_clip_rect((window_size.width, window_size.height), self.texture_size);
+        self.tracer.resize(
+            &self.device,
+            &self.queue,
+            self.texture_size.0,
+            self.texture_size.1,
+        );
     }
 
-    pub fn update(&mut self) {
-        println!("{:?}", self.surface_format);
+    pub fn render_scale(&self) -> f32 {
+        self.settings.render_scale
+    }
+
+    /// Traces at `render_scale` times the window size from now on, clamped
+    /// to the supported range. Scales too large for the device are refused.
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        let settings = Settings {
+            render_scale: render_scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE),
+            ..self.settings.clone()
+        };
+        let texture_size =
+            settings.trace_size((self.surface_config.width, self.surface_config.height));
+        if let Err(message) = check_trace_size(
+            &self.device.limits(),
+            texture_size.0,
+            texture_size.1,
+            self.tracer.aov_enabled(),
+        ) {
+            eprintln!("{}", message);
+            return;
+        }
+        self.settings = settings;
+        self.resize(winit::dpi::PhysicalSize::new(
+            self.surface_config.width,
+            self.surface_config.height,
+        ));
+    }
+
+    pub fn denoise(&self) -> Denoise {
+        self.tracer.denoise()
+    }
+
+    /// Switches the denoiser, or keeps the current mode when the AOVs it
+    /// needs do not fit on the device.
+    pub fn set_denoise(&mut self, denoise: Denoise) {
+        if let Err(message) = self.tracer.set_denoise(&self.device, &self.queue, denoise) {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Whether AOVs can be viewed, which the denoiser also turns on.
+    pub fn aov_enabled(&self) -> bool {
+        self.tracer.aov_enabled()
+    }
+
+    pub fn view(&self) -> View {
+        self.tracer.view()
+    }
+
+    /// Shows the beauty image or one AOV.
+    pub fn set_view(&mut self, view: View) {
+        self.tracer.set_view(&self.queue, view);
+    }
+
+    pub fn tonemap(&self) -> (Tonemap, f32) {
+        self.tracer.tonemap()
+    }
+
+    /// Switches the operator and exposure in stops; the image keeps
+    /// accumulating.
+    pub fn set_tonemap(&mut self, tonemap: Tonemap, exposure: f32) {
+        self.tracer.set_tonemap(&self.queue, tonemap, exposure);
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.tracer.camera()
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.tracer.set_camera(&self.queue, camera);
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.tracer.reset_accumulation();
+    }
+
+    /// Number of frames averaged into the image shown by the last `render`.
+    pub fn frame_count(&self) -> u32 {
+        self.tracer.frame_index()
     }
 
     pub fn render(&mut self) {
@@ -437,15 +377,7 @@ impl Controler {
                 label: Some("Compute-Encoder"),
             });
 
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Compute-Pass"),
-            });
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0, &self.compute_bindgroup0, &[]);
-            compute_pass.set_bind_group(1, &self.compute_bindgroup1, &[]);
-            compute_pass.dispatch(self.work_group_count, 1, 1);
-        }
+        self.tracer.trace(&self.queue, &mut encoder);
 
         self.queue.submit(Some(encoder.finish()));
 
@@ -456,11 +388,11 @@ impl Controler {
             });
         encoder.copy_buffer_to_texture(
             wgpu::ImageCopyBuffer {
-                buffer: &self.result_buffer,
+                buffer: self.tracer.result_buffer(),
                 layout: wgpu::ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: std::num::NonZeroU32::new(4 * TEXTURE_WIDTH),
-                    rows_per_image: std::num::NonZeroU32::new(TEXTURE_HEIGHT),
+                    bytes_per_row: std::num::NonZeroU32::new(self.tracer.result_bytes_per_row()),
+                    rows_per_image: std::num::NonZeroU32::new(self.texture_size.1),
                 },
             },
             wgpu::ImageCopyTexture {
@@ -470,8 +402,8 @@ impl Controler {
                 aspect: wgpu::TextureAspect::All,
             },
             wgpu::Extent3d {
-                width: TEXTURE_WIDTH,
-                height: TEXTURE_HEIGHT,
+                width: self.texture_size.0,
+                height: self.texture_size.1,
                 depth_or_array_layers: 1,
             },
         );
@@ -499,16 +431,66 @@ impl Controler {
             render_pass.set_pipeline(&self.cell_render_pipeline);
             render_pass.set_bind_group(0, &self.cell_render_bind_group, &[]);
             render_pass.set_vertex_buffer(0, self.cell_render_buffer.slice(..));
-            render_pass.draw(0..3, 0..1);
-            render_pass.set_scissor_rect(
-                self.clip_rect.0,
-                self.clip_rect.1,
-                self.clip_rect.2,
-                self.clip_rect.3,
+            // the full-screen triangle covers the viewport, which keeps the
+            // aspect of the trace
+            render_pass.set_viewport(
+                self.clip_rect.0 as f32,
+                self.clip_rect.1 as f32,
+                self.clip_rect.2 as f32,
+                self.clip_rect.3 as f32,
+                0.0,
+                1.0,
             );
+            render_pass.draw(0..3, 0..1);
         }
         self.queue.submit(Some(encoder.finish()));
 
         surface_frame.present();
     }
 }
+
+/// Texture the traced image is copied into for display, and the bind group
+/// sampling it.
+fn create_source_texture(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    blit_buffer: &wgpu::Buffer,
+    texture_size: (u32, u32),
+) -> (wgpu::Texture, wgpu::BindGroup) {
+    let source_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: texture_size.0,
+            height: texture_size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let source_texture_view = source_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Render-Bindgroup"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: blit_buffer.as_entire_binding(),
+            },
+        ],
+    });
+
+    (source_texture, bind_group)
+}
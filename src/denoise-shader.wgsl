@@ -0,0 +1,294 @@
+struct RadianceBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+
+// three entries per pixel, see `systems::aov`
+struct AovBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+
+struct FilterBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+
+struct Camera {
+    eye: vec4<f32>;
+    target: vec4<f32>;
+    up: vec3<f32>;
+    vfov: f32;
+    aspect: f32;
+};
+
+struct DenoiseConfig {
+    // camera of the frame the history was written by
+    previous_camera: Camera;
+    width: u32;
+    height: u32;
+    // frames accumulated before this one, 0 after the camera moved
+    frame_index: u32;
+    temporal: u32;
+};
+
+struct StepConfig {
+    // pixels between the taps of the 5x5 kernel
+    step_width: i32;
+    placholder0: u32;
+    placholder1: u32;
+    placholder2: u32;
+};
+
+// denoised in place by the last pass
+[[group(0), binding(0)]]
+var<storage, read_write> radiance_list: RadianceBuffer;
+[[group(0), binding(1)]]
+var<storage, read> aov_list: AovBuffer;
+[[group(0), binding(2)]]
+var<storage, read> previous_aov_list: AovBuffer;
+// demodulated color and history length of the previous frame
+[[group(0), binding(3)]]
+var<storage, read> history_list: FilterBuffer;
+[[group(0), binding(4)]]
+var<storage, read> source_list: FilterBuffer;
+[[group(0), binding(5)]]
+var<storage, read_write> target_list: FilterBuffer;
+
+[[group(1), binding(0)]]
+var<uniform> denoise_config: DenoiseConfig;
+[[group(1), binding(1)]]
+var<uniform> step_config: StepConfig;
+
+// workgroups along x of a dispatch, larger images take more rows of them,
+// as `tracer::DISPATCH_ROW_GROUPS`
+let DISPATCH_ROW_GROUPS: u32 = 32768u;
+
+// longest history the temporal blend weighs, in frames
+let MAX_HISTORY: f32 = 32.0;
+// smallest weight of the new frame in the temporal blend
+let MIN_BLEND: f32 = 0.2;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn is_miss(pixel_index: u32) -> bool {
+    return aov_list.data[pixel_index * 3u + 2u].w < 0.0;
+}
+
+// lighting without the surface color, so texture detail is not blurred
+fn albedo(pixel_index: u32) -> vec3<f32> {
+    return max(aov_list.data[pixel_index * 3u].xyz, vec3<f32>(0.001, 0.001, 0.001));
+}
+
+// pixel of the previous frame showing `point`, -1 when off screen
+fn reproject(point: vec3<f32>) -> i32 {
+    let camera = denoise_config.previous_camera;
+    let half_height = tan(camera.vfov * 3.141592653 / 360.0);
+    let half_width = half_height * camera.aspect;
+    let forward = normalize(camera.target.xyz - camera.eye.xyz);
+    let right = normalize(cross(forward, camera.up));
+    let up = cross(right, forward);
+
+    let offset = point - camera.eye.xyz;
+    let depth = dot(offset, forward);
+    if (depth <= 0.0) {
+        return -1;
+    }
+    let x = dot(offset, right) / (depth * half_width);
+    let y = dot(offset, up) / (depth * half_height);
+    let col = i32(floor((x + 1.0) * 0.5 * f32(denoise_config.width)));
+    let row = i32(floor((1.0 - y) * 0.5 * f32(denoise_config.height)));
+    if (col < 0 || row < 0 || col >= i32(denoise_config.width) || row >= i32(denoise_config.height)) {
+        return -1;
+    }
+    return row * i32(denoise_config.width) + col;
+}
+
+// demodulates the new frame and, after camera motion, blends it with the
+// reprojected history
+[[stage(compute), workgroup_size(64)]]
+fn temporal([[builtin(global_invocation_id)]] gi_id: vec3<u32>) {
+    let pixel_index = gi_id.y * DISPATCH_ROW_GROUPS * 64u + gi_id.x;
+    if (pixel_index >= denoise_config.width * denoise_config.height) {
+        return;
+    }
+    let radiance = radiance_list.data[pixel_index].xyz;
+    if (is_miss(pixel_index)) {
+        target_list.data[pixel_index] = vec4<f32>(radiance, 1.0);
+        return;
+    }
+
+    var color: vec3<f32> = radiance / albedo(pixel_index);
+    var history_length: f32 = 1.0;
+    if (denoise_config.frame_index > 0u) {
+        // a still camera already averages every frame
+        history_length = min(f32(denoise_config.frame_index + 1u), MAX_HISTORY);
+    } else if (denoise_config.temporal != 0u) {
+        let normal = aov_list.data[pixel_index * 3u + 1u].xyz;
+        let position_object = aov_list.data[pixel_index * 3u + 2u];
+        let depth = aov_list.data[pixel_index * 3u].w;
+        let previous_index = reproject(position_object.xyz);
+        if (previous_index >= 0) {
+            let previous = u32(previous_index);
+            let previous_normal = previous_aov_list.data[previous * 3u + 1u].xyz;
+            let previous_position_object = previous_aov_list.data[previous * 3u + 2u];
+            // the same surface, not one that was hidden behind it
+            if (previous_position_object.w == position_object.w
+                && dot(normal, previous_normal) > 0.9
+                && distance(previous_position_object.xyz, position_object.xyz) < 0.05 * depth) {
+                let history = history_list.data[previous];
+                history_length = min(history.w + 1.0, MAX_HISTORY);
+                color = mix(history.xyz, color, max(1.0 / history_length, MIN_BLEND));
+            }
+        }
+    }
+    target_list.data[pixel_index] = vec4<f32>(color, history_length);
+}
+
+// luminance variance over the 3x3 neighborhood on the same object, which
+// steers how strongly the wavelet passes smooth
+[[stage(compute), workgroup_size(64)]]
+fn variance([[builtin(global_invocation_id)]] gi_id: vec3<u32>) {
+    let pixel_index = gi_id.y * DISPATCH_ROW_GROUPS * 64u + gi_id.x;
+    if (pixel_index >= denoise_config.width * denoise_config.height) {
+        return;
+    }
+    let center = source_list.data[pixel_index];
+    if (is_miss(pixel_index)) {
+        target_list.data[pixel_index] = vec4<f32>(center.xyz, 0.0);
+        return;
+    }
+
+    let width = i32(denoise_config.width);
+    let height = i32(denoise_config.height);
+    let col = i32(pixel_index) % width;
+    let row = i32(pixel_index) / width;
+    let object = aov_list.data[pixel_index * 3u + 2u].w;
+    var sum: f32 = 0.0;
+    var square_sum: f32 = 0.0;
+    var count: f32 = 0.0;
+    var dy: i32 = -1;
+    loop {
+        if (dy > 1) {
+            break;
+        }
+        var dx: i32 = -1;
+        loop {
+            if (dx > 1) {
+                break;
+            }
+            let x = col + dx;
+            let y = row + dy;
+            if (x >= 0 && y >= 0 && x < width && y < height) {
+                let neighbor = u32(y * width + x);
+                if (aov_list.data[neighbor * 3u + 2u].w == object) {
+                    let l = luminance(source_list.data[neighbor].xyz);
+                    sum = sum + l;
+                    square_sum = square_sum + l * l;
+                    count = count + 1.0;
+                }
+            }
+
+            continuing {
+                dx = dx + 1;
+            }
+        }
+
+        continuing {
+            dy = dy + 1;
+        }
+    }
+    let mean = sum / count;
+    target_list.data[pixel_index] = vec4<f32>(center.xyz, max(square_sum / count - mean * mean, 0.0));
+}
+
+// B3 spline, 3/8, 1/4, 1/16 from the center out
+fn kernel_weight(offset: i32) -> f32 {
+    if (offset == 0) {
+        return 0.375;
+    } else if (offset == -1 || offset == 1) {
+        return 0.25;
+    }
+    return 0.0625;
+}
+
+// one level of the edge-avoiding a-trous wavelet transform, color in xyz
+// and its variance in w
+[[stage(compute), workgroup_size(64)]]
+fn atrous([[builtin(global_invocation_id)]] gi_id: vec3<u32>) {
+    let pixel_index = gi_id.y * DISPATCH_ROW_GROUPS * 64u + gi_id.x;
+    if (pixel_index >= denoise_config.width * denoise_config.height) {
+        return;
+    }
+    let center = source_list.data[pixel_index];
+    if (is_miss(pixel_index)) {
+        target_list.data[pixel_index] = center;
+        return;
+    }
+
+    let width = i32(denoise_config.width);
+    let height = i32(denoise_config.height);
+    let col = i32(pixel_index) % width;
+    let row = i32(pixel_index) / width;
+    let step_width = step_config.step_width;
+    let depth = aov_list.data[pixel_index * 3u].w;
+    let normal = aov_list.data[pixel_index * 3u + 1u].xyz;
+    let object = aov_list.data[pixel_index * 3u + 2u].w;
+    let center_luminance = luminance(center.xyz);
+    let luminance_scale = 4.0 * sqrt(center.w) + 0.0001;
+    let depth_scale = 0.01 * depth * f32(step_width) + 0.0001;
+
+    var color_sum: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    var variance_sum: f32 = 0.0;
+    var weight_sum: f32 = 0.0;
+    var dy: i32 = -2;
+    loop {
+        if (dy > 2) {
+            break;
+        }
+        var dx: i32 = -2;
+        loop {
+            if (dx > 2) {
+                break;
+            }
+            let x = col + dx * step_width;
+            let y = row + dy * step_width;
+            if (x >= 0 && y >= 0 && x < width && y < height) {
+                let neighbor = u32(y * width + x);
+                if (aov_list.data[neighbor * 3u + 2u].w == object) {
+                    let tap = source_list.data[neighbor];
+                    let normal_weight = pow(max(dot(normal, aov_list.data[neighbor * 3u + 1u].xyz), 0.0), 128.0);
+                    let depth_weight = exp(-abs(depth - aov_list.data[neighbor * 3u].w) / depth_scale);
+                    let luminance_weight = exp(-abs(center_luminance - luminance(tap.xyz)) / luminance_scale);
+                    let weight = kernel_weight(dx) * kernel_weight(dy) * normal_weight * depth_weight * luminance_weight;
+                    color_sum = color_sum + weight * tap.xyz;
+                    variance_sum = variance_sum + weight * weight * tap.w;
+                    weight_sum = weight_sum + weight;
+                }
+            }
+
+            continuing {
+                dx = dx + 1;
+            }
+        }
+
+        continuing {
+            dy = dy + 1;
+        }
+    }
+    // the center always weighs in, so the sum is never zero
+    target_list.data[pixel_index] = vec4<f32>(color_sum / weight_sum, variance_sum / (weight_sum * weight_sum));
+}
+
+// puts the surface color back and replaces the radiance
+[[stage(compute), workgroup_size(64)]]
+fn modulate([[builtin(global_invocation_id)]] gi_id: vec3<u32>) {
+    let pixel_index = gi_id.y * DISPATCH_ROW_GROUPS * 64u + gi_id.x;
+    if (pixel_index >= denoise_config.width * denoise_config.height) {
+        return;
+    }
+    var color: vec3<f32> = source_list.data[pixel_index].xyz;
+    if (!is_miss(pixel_index)) {
+        color = color * albedo(pixel_index);
+    }
+    radiance_list.data[pixel_index] = vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,322 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::{entity::Camera, systems::aov::AOV_STRIDE, tracer::work_group_count};
+
+/// Levels of the wavelet transform, with the tap spacing doubling from 1
+/// to 16 pixels.
+const ATROUS_ITERATIONS: u32 = 5;
+
+/// Uniform data shared by every denoise pass.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct DenoiseConfig {
+    previous_camera: Camera,
+    width: u32,
+    height: u32,
+    frame_index: u32,
+    temporal: u32,
+}
+
+unsafe impl Zeroable for DenoiseConfig {}
+unsafe impl Pod for DenoiseConfig {}
+
+/// Edge-aware à-trous wavelet filter in the style of SVGF, run on the
+/// radiance buffer before tonemapping. The albedo, normal, depth and object
+/// AOVs guide it, and after camera motion the previous frame is reprojected
+/// and blended in.
+pub struct Denoiser {
+    temporal_pipeline: wgpu::ComputePipeline,
+    variance_pipeline: wgpu::ComputePipeline,
+    atrous_pipeline: wgpu::ComputePipeline,
+    modulate_pipeline: wgpu::ComputePipeline,
+    bindgroup0_layout: wgpu::BindGroupLayout,
+    bindgroup1: wgpu::BindGroup,
+    config_buffer: wgpu::Buffer,
+    /// Offset between the step widths of consecutive levels.
+    step_stride: u32,
+    /// Camera of the last denoised frame, which its history was seen from.
+    previous_camera: Camera,
+    /// Whether history from before a camera move is blended in.
+    pub temporal: bool,
+}
+
+impl Denoiser {
+    pub fn new(device: &wgpu::Device, camera: Camera) -> Self {
+        let shader_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Denoise-Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("denoise-shader.wgsl").into()),
+        });
+
+        let storage_layout_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: std::num::NonZeroU64::new(16),
+            },
+            count: None,
+        };
+        let bindgroup0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage_layout_entry(0, false),
+                storage_layout_entry(1, true),
+                storage_layout_entry(2, true),
+                storage_layout_entry(3, true),
+                storage_layout_entry(4, true),
+                storage_layout_entry(5, false),
+            ],
+        });
+
+        let config_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Denoise-Config-Buffer"),
+            size: std::mem::size_of::<DenoiseConfig>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        // one step width per level, each at an offset uniforms can be bound at
+        let step_stride = device.limits().min_uniform_buffer_offset_alignment;
+        let mut step_buffer_data = vec![0u32; (step_stride / 4 * ATROUS_ITERATIONS) as usize];
+        for level in 0..ATROUS_ITERATIONS {
+            step_buffer_data[(step_stride / 4 * level) as usize] = 1 << level;
+        }
+        let step_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Denoise-Step-Buffer"),
+            contents: bytemuck::cast_slice(&step_buffer_data),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bindgroup1_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: std::num::NonZeroU64::new(
+                            std::mem::size_of::<DenoiseConfig>() as u64,
+                        ),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: std::num::NonZeroU64::new(16),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bindgroup1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Denoise"),
+            layout: &bindgroup1_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: config_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &step_buffer,
+                        offset: 0,
+                        size: std::num::NonZeroU64::new(16),
+                    }),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bindgroup0_layout, &bindgroup1_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point,
+            })
+        };
+
+        Denoiser {
+            temporal_pipeline: create_pipeline("temporal"),
+            variance_pipeline: create_pipeline("variance"),
+            atrous_pipeline: create_pipeline("atrous"),
+            modulate_pipeline: create_pipeline("modulate"),
+            bindgroup0_layout,
+            bindgroup1,
+            config_buffer,
+            step_stride,
+            previous_camera: camera,
+            temporal: false,
+        }
+    }
+
+    /// Filters the radiance of `buffers` in place, guided by the AOVs in
+    /// `aov_buffer`. `frame_index` counts the frames accumulated before
+    /// this one with `camera`.
+    pub fn denoise(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        buffers: &DenoiseBuffers,
+        aov_buffer: &wgpu::Buffer,
+        camera: Camera,
+        frame_index: u32,
+    ) {
+        let config = DenoiseConfig {
+            previous_camera: self.previous_camera,
+            width: buffers.size.0,
+            height: buffers.size.1,
+            frame_index,
+            temporal: self.temporal as u32,
+        };
+        queue.write_buffer(&self.config_buffer, 0, bytemuck::bytes_of(&config));
+        self.previous_camera = camera;
+        let work_group_count = buffers.work_group_count();
+
+        // demodulated and reprojected color into filter 0
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Temporal-Pass"),
+            });
+            compute_pass.set_pipeline(&self.temporal_pipeline);
+            compute_pass.set_bind_group(0, &buffers.bind_groups[1], &[]);
+            compute_pass.set_bind_group(1, &self.bindgroup1, &[0]);
+            compute_pass.dispatch(work_group_count.0, work_group_count.1, 1);
+        }
+        // kept before filtering, as the next frame's history
+        encoder.copy_buffer_to_buffer(
+            &buffers.filter_buffers[0],
+            0,
+            &buffers.history_buffer,
+            0,
+            buffers.total_pixel * 16,
+        );
+        encoder.copy_buffer_to_buffer(
+            aov_buffer,
+            0,
+            &buffers.previous_aov_buffer,
+            0,
+            buffers.total_pixel * AOV_STRIDE as u64 * 16,
+        );
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Atrous-Pass"),
+        });
+        compute_pass.set_pipeline(&self.variance_pipeline);
+        compute_pass.set_bind_group(0, &buffers.bind_groups[0], &[]);
+        compute_pass.set_bind_group(1, &self.bindgroup1, &[0]);
+        compute_pass.dispatch(work_group_count.0, work_group_count.1, 1);
+
+        // ping-pong between the filter buffers, starting from filter 1
+        compute_pass.set_pipeline(&self.atrous_pipeline);
+        for level in 0..ATROUS_ITERATIONS {
+            compute_pass.set_bind_group(0, &buffers.bind_groups[(level as usize + 1) % 2], &[]);
+            compute_pass.set_bind_group(1, &self.bindgroup1, &[self.step_stride * level]);
+            compute_pass.dispatch(work_group_count.0, work_group_count.1, 1);
+        }
+
+        compute_pass.set_pipeline(&self.modulate_pipeline);
+        compute_pass.set_bind_group(
+            0,
+            &buffers.bind_groups[(ATROUS_ITERATIONS as usize + 1) % 2],
+            &[],
+        );
+        compute_pass.set_bind_group(1, &self.bindgroup1, &[0]);
+        compute_pass.dispatch(work_group_count.0, work_group_count.1, 1);
+    }
+}
+
+/// Per-pixel buffers of the denoiser, reallocated with the resolution.
+pub struct DenoiseBuffers {
+    previous_aov_buffer: wgpu::Buffer,
+    history_buffer: wgpu::Buffer,
+    filter_buffers: [wgpu::Buffer; 2],
+    /// Reading filter 0 and writing filter 1, then the other way around.
+    bind_groups: [wgpu::BindGroup; 2],
+    size: (u32, u32),
+    total_pixel: u64,
+}
+
+impl DenoiseBuffers {
+    /// Filters `radiance_buffer` guided by the AOVs in `aov_buffer`, both
+    /// holding `size` pixels.
+    pub fn new(
+        device: &wgpu::Device,
+        denoiser: &Denoiser,
+        radiance_buffer: &wgpu::Buffer,
+        aov_buffer: &wgpu::Buffer,
+        size: (u32, u32),
+    ) -> Self {
+        let total_pixel = size.0 as u64 * size.1 as u64;
+        let create_buffer = |label: &str, size: u64| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let previous_aov_buffer =
+            create_buffer("Previous-Aov-Buffer", total_pixel * AOV_STRIDE as u64 * 16);
+        let history_buffer = create_buffer("History-Buffer", total_pixel * 16);
+        let filter_buffers = [
+            create_buffer("Filter-Buffer-0", total_pixel * 16),
+            create_buffer("Filter-Buffer-1", total_pixel * 16),
+        ];
+
+        let create_bind_group = |source: &wgpu::Buffer, target: &wgpu::Buffer| {
+            let buffers = [
+                radiance_buffer,
+                aov_buffer,
+                &previous_aov_buffer,
+                &history_buffer,
+                source,
+                target,
+            ];
+            let entries: Vec<wgpu::BindGroupEntry> = buffers
+                .iter()
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect();
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Denoise-Bindgroup"),
+                layout: &denoiser.bindgroup0_layout,
+                entries: &entries,
+            })
+        };
+        let bind_groups = [
+            create_bind_group(&filter_buffers[0], &filter_buffers[1]),
+            create_bind_group(&filter_buffers[1], &filter_buffers[0]),
+        ];
+
+        DenoiseBuffers {
+            previous_aov_buffer,
+            history_buffer,
+            filter_buffers,
+            bind_groups,
+            size,
+            total_pixel,
+        }
+    }
+
+    fn work_group_count(&self) -> (u32, u32) {
+        work_group_count(self.total_pixel)
+    }
+}
@@ -1,3 +1,8 @@
+mod camera_impl;
+mod delta_light_impl;
+mod instance_impl;
+mod material_impl;
+mod mesh_impl;
 mod panel_impl;
 mod sphere_impl;
 
@@ -5,15 +10,128 @@ mod sphere_impl;
 #[derive(Debug, Clone, Copy)]
 pub struct Sphere {
     pub position: [f32; 4],
-    pub color: [f32; 3],
     pub radius: f32,
+    /// Index into the material list.
+    pub material: u32,
+    pub _place_holder: [f32; 2],
 }
 
+/// Parallelogram spanned by `edge_u` and `edge_v` from `origin`, seen only
+/// from the side its normal, `edge_u × edge_v`, points to.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Panel {
-    pub points: [f32; 8],
+    pub origin: [f32; 4],
+    pub edge_u: [f32; 4],
+    pub edge_v: [f32; 4],
+    pub normal: [f32; 4],
+    /// Index into the material list.
+    pub material: u32,
+    pub _place_holder: [f32; 3],
+}
+
+/// Light without area: a point, a spot or a distant sun. It cannot be hit
+/// by rays, only sampled with shadow rays.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DeltaLight {
+    /// Unused by directional lights.
+    pub position: [f32; 4],
+    /// Where spot and directional lights shine to, unused by point lights.
+    pub direction: [f32; 4],
+    /// Radiant intensity of point and spot lights, irradiance of directional
+    /// ones.
+    pub intensity: [f32; 3],
+    pub kind: u32,
+    /// Cosines of the spot cone half angles: full intensity inside the inner
+    /// cone, fading to none at the outer one.
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    pub _place_holder: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub eye: [f32; 4],
+    pub target: [f32; 4],
+    pub up: [f32; 3],
+    /// Vertical field of view in degrees.
+    pub vfov: f32,
+    pub aspect: f32,
+    pub _place_holder: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub position: [f32; 4],
     pub normal: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Triangle {
+    /// Indices into the vertex list the triangle belongs to.
+    pub indices: [u32; 3],
+    /// Index into the material list.
+    pub material: u32,
+}
+
+/// Surface description shared by every entity that references it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Material {
+    /// Matte surface scattering like a cosine lobe.
+    Lambertian { color: [f32; 3] },
+    /// Mirror whose reflection is blurred by `fuzz`, from 0 to 1.
+    Metal { color: [f32; 3], fuzz: f32 },
+    /// Clear glass-like surface refracting with index `ior`.
+    Dielectric { ior: f32 },
+    /// Light source; does not scatter.
+    Emissive { emission: [f32; 3] },
+    /// Metallic-roughness surface: a GGX specular lobe over a Lambertian
+    /// base, both from 0 to 1 like in common PBR tools.
+    Pbr {
+        base_color: [f32; 3],
+        roughness: f32,
+        metallic: f32,
+    },
+}
+
+/// `Material` as laid out in the gpu material list.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialData {
+    /// Albedo, or radiance for emissive materials.
     pub color: [f32; 3],
-    pub _place_holder: f32,
+    pub kind: u32,
+    /// Fuzz for metals, index of refraction for dielectrics, roughness for
+    /// PBR materials.
+    pub parameter: f32,
+    pub metallic: f32,
+    pub _place_holder: [f32; 2],
+}
+
+/// Indexed triangle list, e.g. loaded from an OBJ file.
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub triangles: Vec<Triangle>,
+}
+
+/// Placement of a mesh in the triangle list. Rays are moved into the
+/// object space of the mesh instead of copying its triangles, so one mesh
+/// can be placed many times.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    /// Column-major, as WGSL `mat4x4<f32>` expects.
+    pub object_to_world: [[f32; 4]; 4],
+    pub world_to_object: [[f32; 4]; 4],
+    pub first_triangle: u32,
+    pub triangle_count: u32,
+    /// Root of the hierarchy over the triangles of the mesh, unused
+    /// without one.
+    pub bvh_root: u32,
+    pub _place_holder: u32,
 }
@@ -0,0 +1,91 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::Camera;
+use crate::systems::math::{add, cross, dot, length, normalize, scale, sub};
+
+impl Camera {
+    pub fn new(eye: [f32; 3], target: [f32; 3], up: [f32; 3], vfov: f32, aspect: f32) -> Self {
+        let mut p_eye = [0.0f32; 4];
+        p_eye[..3].clone_from_slice(&eye[..]);
+        p_eye[3] = 1.0;
+
+        let mut p_target = [0.0f32; 4];
+        p_target[..3].clone_from_slice(&target[..]);
+        p_target[3] = 1.0;
+
+        Camera {
+            eye: p_eye,
+            target: p_target,
+            up,
+            vfov,
+            aspect,
+            _place_holder: [0.0; 3],
+        }
+    }
+
+    pub fn position(&self) -> [f32; 3] {
+        [self.eye[0], self.eye[1], self.eye[2]]
+    }
+
+    pub fn look_at(&self) -> [f32; 3] {
+        [self.target[0], self.target[1], self.target[2]]
+    }
+
+    /// Unit vectors pointing forward, right and up from the eye.
+    pub fn basis(&self) -> ([f32; 3], [f32; 3], [f32; 3]) {
+        let forward = normalize(sub(self.look_at(), self.position()));
+        let right = normalize(cross(forward, self.up));
+        let up = cross(right, forward);
+        (forward, right, up)
+    }
+
+    /// Moves eye and target together by `delta` given in the camera basis
+    /// (right, up, forward).
+    pub fn translate(&mut self, delta: [f32; 3]) {
+        let (forward, right, up) = self.basis();
+        let offset = add(
+            add(scale(right, delta[0]), scale(up, delta[1])),
+            scale(forward, delta[2]),
+        );
+        let eye = add(self.position(), offset);
+        let target = add(self.look_at(), offset);
+        self.eye[..3].clone_from_slice(&eye[..]);
+        self.target[..3].clone_from_slice(&target[..]);
+    }
+
+    /// Rotates the eye around the target, in radians, keeping the distance.
+    /// Pitch is measured against the `up` vector and stops short of the poles.
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        let up = normalize(self.up);
+        let offset = sub(self.position(), self.look_at());
+        let distance = length(offset);
+        let direction = normalize(offset);
+
+        let height = dot(direction, up);
+        let limit = 89.0f32.to_radians();
+        let new_pitch = (height.clamp(-1.0, 1.0).asin() + pitch).clamp(-limit, limit);
+
+        let flat = normalize(sub(direction, scale(up, height)));
+        let side = cross(up, flat);
+        let (sin_yaw, cos_yaw) = yaw.sin_cos();
+        let flat = add(scale(flat, cos_yaw), scale(side, sin_yaw));
+
+        let direction = add(scale(flat, new_pitch.cos()), scale(up, new_pitch.sin()));
+        let eye = add(self.look_at(), scale(direction, distance));
+        self.eye[..3].clone_from_slice(&eye[..]);
+    }
+
+    /// Scales the eye-target distance by `factor`.
+    pub fn zoom(&mut self, factor: f32) {
+        let offset = sub(self.position(), self.look_at());
+        let eye = add(self.look_at(), scale(offset, factor));
+        self.eye[..3].clone_from_slice(&eye[..]);
+    }
+
+    pub fn distance(&self) -> f32 {
+        length(sub(self.position(), self.look_at()))
+    }
+}
+
+unsafe impl Zeroable for Camera {}
+unsafe impl Pod for Camera {}
@@ -0,0 +1,67 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::DeltaLight;
+use crate::systems::math::normalize;
+
+/// Values of `DeltaLight::kind`, matching the constants in the compute shader.
+pub const LIGHT_POINT: u32 = 0;
+pub const LIGHT_SPOT: u32 = 1;
+pub const LIGHT_DIRECTIONAL: u32 = 2;
+
+impl DeltaLight {
+    pub fn point(position: [f32; 3], intensity: [f32; 3]) -> Self {
+        DeltaLight::new(LIGHT_POINT, position, [0.0; 3], intensity, -1.0, -1.0)
+    }
+
+    /// The angles are cone half angles in degrees, `inner_angle` at most
+    /// `outer_angle`.
+    pub fn spot(
+        position: [f32; 3],
+        direction: [f32; 3],
+        intensity: [f32; 3],
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        DeltaLight::new(
+            LIGHT_SPOT,
+            position,
+            normalize(direction),
+            intensity,
+            inner_angle.to_radians().cos(),
+            outer_angle.to_radians().cos(),
+        )
+    }
+
+    pub fn directional(direction: [f32; 3], irradiance: [f32; 3]) -> Self {
+        DeltaLight::new(
+            LIGHT_DIRECTIONAL,
+            [0.0; 3],
+            normalize(direction),
+            irradiance,
+            -1.0,
+            -1.0,
+        )
+    }
+
+    fn new(
+        kind: u32,
+        position: [f32; 3],
+        direction: [f32; 3],
+        intensity: [f32; 3],
+        cos_inner: f32,
+        cos_outer: f32,
+    ) -> Self {
+        DeltaLight {
+            position: [position[0], position[1], position[2], 1.0],
+            direction: [direction[0], direction[1], direction[2], 0.0],
+            intensity,
+            kind,
+            cos_inner,
+            cos_outer,
+            _place_holder: [0.0; 2],
+        }
+    }
+}
+
+unsafe impl Zeroable for DeltaLight {}
+unsafe impl Pod for DeltaLight {}
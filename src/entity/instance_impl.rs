@@ -0,0 +1,28 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::Instance;
+use crate::systems::transform::Transform;
+
+impl Instance {
+    pub fn new(transform: &Transform, first_triangle: u32, triangle_count: u32) -> Self {
+        Instance {
+            object_to_world: transform.columns(),
+            world_to_object: transform.inverse().columns(),
+            first_triangle,
+            triangle_count,
+            bvh_root: 0,
+            _place_holder: 0,
+        }
+    }
+
+    pub fn point_to_world(&self, point: [f32; 4]) -> [f32; 3] {
+        [0, 1, 2].map(|row| {
+            (0..4)
+                .map(|column| self.object_to_world[column][row] * point[column])
+                .sum()
+        })
+    }
+}
+
+unsafe impl Zeroable for Instance {}
+unsafe impl Pod for Instance {}
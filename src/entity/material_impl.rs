@@ -0,0 +1,37 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{Material, MaterialData};
+
+/// Values of `MaterialData::kind`, matching the constants in the compute shader.
+pub const MATERIAL_LAMBERTIAN: u32 = 0;
+pub const MATERIAL_METAL: u32 = 1;
+pub const MATERIAL_DIELECTRIC: u32 = 2;
+pub const MATERIAL_EMISSIVE: u32 = 3;
+pub const MATERIAL_PBR: u32 = 4;
+
+impl Material {
+    pub fn data(&self) -> MaterialData {
+        let (color, kind, parameter, metallic) = match *self {
+            Material::Lambertian { color } => (color, MATERIAL_LAMBERTIAN, 0.0, 0.0),
+            Material::Metal { color, fuzz } => (color, MATERIAL_METAL, fuzz, 0.0),
+            Material::Dielectric { ior } => ([1.0; 3], MATERIAL_DIELECTRIC, ior, 0.0),
+            Material::Emissive { emission } => (emission, MATERIAL_EMISSIVE, 0.0, 0.0),
+            Material::Pbr {
+                base_color,
+                roughness,
+                metallic,
+            } => (base_color, MATERIAL_PBR, roughness, metallic),
+        };
+
+        MaterialData {
+            color,
+            kind,
+            parameter,
+            metallic,
+            _place_holder: [0.0; 2],
+        }
+    }
+}
+
+unsafe impl Zeroable for MaterialData {}
+unsafe impl Pod for MaterialData {}
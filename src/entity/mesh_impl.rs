@@ -0,0 +1,45 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{Mesh, Triangle, Vertex};
+
+impl Vertex {
+    pub fn new(pos: [f32; 3], normal: [f32; 3]) -> Self {
+        let mut position = [0.0f32; 4];
+        position[..3].clone_from_slice(&pos[..]);
+        position[3] = 1.0;
+
+        let mut v_normal = [0.0f32; 4];
+        v_normal[..3].clone_from_slice(&normal[..]);
+        v_normal[3] = 0.0;
+
+        Vertex {
+            position,
+            normal: v_normal,
+        }
+    }
+}
+
+impl Triangle {
+    pub fn new(indices: [u32; 3], material: u32) -> Self {
+        Triangle { indices, material }
+    }
+}
+
+impl Mesh {
+    /// Appends `other`, shifting its indices past the vertices already here.
+    pub fn append(&mut self, other: &Mesh) {
+        let base = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.triangles
+            .extend(other.triangles.iter().map(|triangle| Triangle {
+                indices: triangle.indices.map(|index| index + base),
+                ..*triangle
+            }));
+    }
+}
+
+unsafe impl Zeroable for Vertex {}
+unsafe impl Pod for Vertex {}
+
+unsafe impl Zeroable for Triangle {}
+unsafe impl Pod for Triangle {}
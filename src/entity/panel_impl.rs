@@ -1,41 +1,78 @@
 use bytemuck::{Pod, Zeroable};
 
 use super::Panel;
+use crate::systems::{
+    math::{cross, length},
+    transform::Transform,
+};
 
 impl Panel {
-    pub fn new(point0: [f32; 3], point1: [f32; 3], normal: [f32; 3], color: [f32; 3]) -> Self {
-        let mut points = [0.0f32; 8];
-        points[..3].clone_from_slice(&point0[..]);
-        points[3] = 1.0;
-        points[4..7].clone_from_slice(&point1[..]);
-        points[7] = 1.0;
-
-        let mut p_normal = [0.0f32; 4];
-        p_normal[..3].clone_from_slice(&normal[..]);
-        p_normal[3] = 0.0;
+    pub fn new(origin: [f32; 3], edge_u: [f32; 3], edge_v: [f32; 3], material: u32) -> Self {
+        let normal = cross(edge_u, edge_v);
+        let length = length(normal);
 
         Panel {
-            points,
-            normal: p_normal,
-            color,
-            _place_holder: -10.0,
+            origin: [origin[0], origin[1], origin[2], 1.0],
+            edge_u: [edge_u[0], edge_u[1], edge_u[2], 0.0],
+            edge_v: [edge_v[0], edge_v[1], edge_v[2], 0.0],
+            normal: [
+                normal[0] / length,
+                normal[1] / length,
+                normal[2] / length,
+                0.0,
+            ],
+            material,
+            _place_holder: [-10.0; 3],
         }
     }
 
-    pub fn raw_data(&self) -> Vec<f32> {
-        let mut res = vec![];
-        for num in self.points {
-            res.push(num);
-        }
-        for num in self.normal {
-            res.push(num);
-        }
-        for num in self.color {
-            res.push(num);
+    /// Rectangle spanning `min` to `max` in the plane across the axis of
+    /// `normal`, facing the way `normal` points along it.
+    pub fn axis_aligned(min: [f32; 3], max: [f32; 3], normal: [f32; 3], material: u32) -> Self {
+        let axis = (0..3)
+            .max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs()))
+            .unwrap_or(2);
+        // the next two axes in order give a normal along +axis
+        let mut edge_u = [0.0; 3];
+        let mut edge_v = [0.0; 3];
+        edge_u[(axis + 1) % 3] = max[(axis + 1) % 3] - min[(axis + 1) % 3];
+        edge_v[(axis + 2) % 3] = max[(axis + 2) % 3] - min[(axis + 2) % 3];
+        if normal[axis] < 0.0 {
+            std::mem::swap(&mut edge_u, &mut edge_v);
         }
-        res.push(-2.0);
 
-        res
+        Panel::new(min, edge_u, edge_v, material)
+    }
+
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let origin = [self.origin[0], self.origin[1], self.origin[2]];
+        let edge_u = [self.edge_u[0], self.edge_u[1], self.edge_u[2]];
+        let edge_v = [self.edge_v[0], self.edge_v[1], self.edge_v[2]];
+        Panel::new(
+            transform.transform_point(origin),
+            transform.transform_vector(edge_u),
+            transform.transform_vector(edge_v),
+            self.material,
+        )
+    }
+
+    pub fn area(&self) -> f32 {
+        length(cross(
+            [self.edge_u[0], self.edge_u[1], self.edge_u[2]],
+            [self.edge_v[0], self.edge_v[1], self.edge_v[2]],
+        ))
+    }
+
+    pub fn corners(&self) -> [[f32; 3]; 4] {
+        let point = |u: f32, v: f32| {
+            [0, 1, 2].map(|axis| self.origin[axis] + u * self.edge_u[axis] + v * self.edge_v[axis])
+        };
+        [
+            point(0.0, 0.0),
+            point(1.0, 0.0),
+            point(0.0, 1.0),
+            point(1.0, 1.0),
+        ]
     }
 }
 
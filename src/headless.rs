@@ -0,0 +1,197 @@
+use image::imageops::FilterType;
+
+use crate::{
+    settings::{ScaleFilter, Settings},
+    systems::{
+        aov::{Aov, AOV_STRIDE},
+        scene::Scene,
+    },
+    tracer::{check_environment_size, check_trace_size, required_limits, Tracer},
+};
+
+/// Runs the compute pipeline without a window or surface and reads the
+/// frame back to the CPU.
+pub struct HeadlessControler {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    tracer: Tracer,
+    readback_buffer: wgpu::Buffer,
+    total_pixel: u64,
+    trace_size: (u32, u32),
+    image_size: (u32, u32),
+    scale_filter: ScaleFilter,
+}
+
+impl HeadlessControler {
+    /// Fails with a message ready to print when there is no adapter, it
+    /// lacks storage buffers or the trace or environment map is too large
+    /// for the device.
+    pub async fn new(settings: &Settings, scene: &Scene) -> Result<Self, String> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: settings.force_fallback_adapter,
+            })
+            .await
+            .ok_or_else(|| {
+                "No suitable adapter found (try --fallback / a software adapter)".to_string()
+            })?;
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::empty(),
+                    limits: required_limits(&adapter)?,
+                },
+                None,
+            )
+            .await
+            .map_err(|e| format!("Failed to create device: {}", e))?;
+
+        let image_size = (settings.width, settings.height);
+        let trace_size = settings.trace_size(image_size);
+        check_trace_size(
+            &device.limits(),
+            trace_size.0,
+            trace_size.1,
+            settings.aov_enabled(),
+        )?;
+        check_environment_size(&device.limits(), scene)?;
+        let trace_settings = Settings {
+            width: trace_size.0,
+            height: trace_size.1,
+            ..settings.clone()
+        };
+        let total_pixel = trace_settings.total_pixel();
+        let tracer = Tracer::new(&device, &queue, &trace_settings, scene);
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback-Buffer"),
+            // large enough for the widest pixels, the AOVs or the radiance,
+            // and for the padded rows of the result
+            size: (total_pixel
+                * if settings.aov {
+                    AOV_STRIDE as u64 * 16
+                } else {
+                    16
+                })
+            .max(tracer.result_bytes_per_row() as u64 * trace_size.1 as u64),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(HeadlessControler {
+            device,
+            queue,
+            tracer,
+            readback_buffer,
+            total_pixel,
+            trace_size,
+            image_size,
+            scale_filter: settings.scale_filter,
+        })
+    }
+
+    /// Traces one frame and returns it as tightly packed rgba8 rows at the
+    /// image size, rescaled from the render scale if needed.
+    pub fn render(&mut self) -> Vec<u8> {
+        self.trace();
+        let bytes_per_row = self.tracer.result_bytes_per_row() as usize;
+        let data = self.read_back(
+            self.tracer.result_buffer(),
+            (bytes_per_row * self.trace_size.1 as usize) as u64,
+        );
+        // result_buffer holds bgra8 texels in padded rows
+        let pixels = data
+            .chunks_exact(bytes_per_row)
+            .flat_map(|row| row[..self.trace_size.0 as usize * 4].chunks_exact(4))
+            .flat_map(|bgra| [bgra[2], bgra[1], bgra[0], 255])
+            .collect();
+        if self.trace_size == self.image_size {
+            return pixels;
+        }
+        let image = image::RgbaImage::from_raw(self.trace_size.0, self.trace_size.1, pixels)
+            .expect("Readback size mismatch");
+        image::imageops::resize(&image, self.image_size.0, self.image_size.1, self.filter())
+            .into_raw()
+    }
+
+    /// Traces one frame and returns its linear radiance as rgba rows at the
+    /// image size, without tonemapping.
+    pub fn render_radiance(&mut self) -> Vec<[f32; 4]> {
+        self.trace();
+        let data = self.read_back(self.tracer.radiance_buffer(), self.total_pixel * 16);
+        self.rescale(bytemuck::pod_collect_to_vec(&data), self.filter())
+    }
+
+    /// Every AOV of the last frame at the image size, see `systems::aov`.
+    /// Only valid after a render with AOVs enabled.
+    pub fn aovs(&self) -> Vec<(Aov, Vec<[f32; 4]>)> {
+        let data = self.read_back(
+            self.tracer.aov_buffer(),
+            self.total_pixel * AOV_STRIDE as u64 * 16,
+        );
+        let data: Vec<[f32; 4]> = bytemuck::pod_collect_to_vec(&data);
+        Aov::ALL
+            .into_iter()
+            // indices and depths must not be blended across edges
+            .map(|aov| {
+                let pixels = self.rescale(aov.extract(&data), FilterType::Nearest);
+                (aov, pixels)
+            })
+            .collect()
+    }
+
+    fn trace(&mut self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Compute-Encoder"),
+            });
+        self.tracer.trace(&self.queue, &mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// Copies the first `size` bytes of `source` back to the CPU.
+    fn read_back(&self, source: &wgpu::Buffer, size: u64) -> Vec<u8> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback-Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(source, 0, &self.readback_buffer, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let buffer_slice = self.readback_buffer.slice(..size);
+        let mapping = buffer_slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).expect("Error map readback buffer");
+        let data = buffer_slice.get_mapped_range().to_vec();
+        self.readback_buffer.unmap();
+
+        data
+    }
+
+    /// Scales float rgba pixels from the trace size to the image size.
+    fn rescale(&self, pixels: Vec<[f32; 4]>, filter: FilterType) -> Vec<[f32; 4]> {
+        if self.trace_size == self.image_size {
+            return pixels;
+        }
+        let image = image::Rgba32FImage::from_raw(
+            self.trace_size.0,
+            self.trace_size.1,
+            bytemuck::cast_vec(pixels),
+        )
+        .expect("Readback size mismatch");
+        let resized = image::imageops::resize(&image, self.image_size.0, self.image_size.1, filter);
+        bytemuck::pod_collect_to_vec(resized.as_raw())
+    }
+
+    fn filter(&self) -> FilterType {
+        match self.scale_filter {
+            ScaleFilter::Nearest => FilterType::Nearest,
+            ScaleFilter::Linear => FilterType::Triangle,
+        }
+    }
+}
@@ -1,24 +1,68 @@
 use std::time::Instant;
 
-use settings::{WINDOW_HEIGHT, WINDOW_TITLE, WINDOW_WIDHT};
+use settings::{ArgsError, Settings, USAGE, WINDOW_TITLE};
+use systems::{camera_control::CameraControl, scene::Scene};
 
 mod controler;
+mod denoiser;
 mod entity;
+mod headless;
 mod settings;
 mod systems;
+mod tracer;
 
 fn main() {
     env_logger::init();
+    let settings = match Settings::from_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(ArgsError::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(ArgsError::Invalid(message)) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    // the benchmark generates its own scene
+    if let Some(sphere_count) = settings.benchmark {
+        run_benchmark(&settings, sphere_count);
+        return;
+    }
+    let scene = match &settings.scene_path {
+        Some(path) => match systems::scene::load_scene(path) {
+            Ok(scene) => scene,
+            Err(err) => {
+                eprintln!("Error load scene {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None => systems::generator::generate_scene(),
+    };
+    if settings.headless {
+        render_headless(&settings, &scene);
+        return;
+    }
+
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
         .with_inner_size(winit::dpi::Size::Physical(winit::dpi::PhysicalSize {
-            width: WINDOW_WIDHT,
-            height: WINDOW_HEIGHT,
+            width: settings.width,
+            height: settings.height,
         }))
         .with_title(WINDOW_TITLE)
         .build(&event_loop)
         .unwrap();
-    let mut controler = pollster::block_on(controler::Controler::new(&window));
+    let mut controler =
+        match pollster::block_on(controler::Controler::new(&window, &settings, &scene)) {
+            Ok(controler) => controler,
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }
+        };
+    let mut camera_control = CameraControl::default();
+    let mut last_frame = Instant::now();
     event_loop.run(move |event, _, control_flow| match event {
         winit::event::Event::WindowEvent { event, .. } => match event {
             winit::event::WindowEvent::CloseRequested => {
@@ -37,24 +81,210 @@ fn main() {
                     virtual_keycode: Some(winit::event::VirtualKeyCode::Space),
                     ..
                 } => {
-                    println!("Request redraw.");
-                    controler.update();
-                    window.request_redraw();
+                    println!("Reset accumulation.");
+                    controler.reset_accumulation();
+                }
+                winit::event::KeyboardInput {
+                    state: winit::event::ElementState::Pressed,
+                    virtual_keycode: Some(winit::event::VirtualKeyCode::V),
+                    ..
+                } => {
+                    if controler.aov_enabled() {
+                        controler.set_view(controler.view().next());
+                        println!("View {:?}.", controler.view());
+                    } else {
+                        println!("AOVs are off, start with --aov to view them.");
+                    }
+                }
+                winit::event::KeyboardInput {
+                    state: winit::event::ElementState::Pressed,
+                    virtual_keycode: Some(winit::event::VirtualKeyCode::N),
+                    ..
+                } => {
+                    controler.set_denoise(controler.denoise().next());
+                    println!("Denoise {:?}.", controler.denoise());
+                }
+                winit::event::KeyboardInput {
+                    state: winit::event::ElementState::Pressed,
+                    virtual_keycode: Some(winit::event::VirtualKeyCode::T),
+                    ..
+                } => {
+                    let (tonemap, exposure) = controler.tonemap();
+                    controler.set_tonemap(tonemap.next(), exposure);
+                    println!("Tonemap {:?}.", tonemap.next());
+                }
+                winit::event::KeyboardInput {
+                    state: winit::event::ElementState::Pressed,
+                    virtual_keycode:
+                        Some(
+                            keycode @ (winit::event::VirtualKeyCode::Minus
+                            | winit::event::VirtualKeyCode::Equals),
+                        ),
+                    ..
+                } => {
+                    let step = if keycode == winit::event::VirtualKeyCode::Minus {
+                        -0.5
+                    } else {
+                        0.5
+                    };
+                    let (tonemap, exposure) = controler.tonemap();
+                    controler.set_tonemap(tonemap, exposure + step);
+                    println!("Exposure {:+} stops.", exposure + step);
+                }
+                winit::event::KeyboardInput {
+                    state: winit::event::ElementState::Pressed,
+                    virtual_keycode:
+                        Some(
+                            keycode @ (winit::event::VirtualKeyCode::LBracket
+                            | winit::event::VirtualKeyCode::RBracket),
+                        ),
+                    ..
+                } => {
+                    let factor = if keycode == winit::event::VirtualKeyCode::LBracket {
+                        0.5
+                    } else {
+                        2.0
+                    };
+                    controler.set_render_scale(controler.render_scale() * factor);
+                    println!("Render scale {}.", controler.render_scale());
+                }
+                winit::event::KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                } => {
+                    camera_control.process_keyboard(keycode, state);
                 }
                 _ => {}
             },
+            winit::event::WindowEvent::Resized(size) => {
+                controler.resize(size);
+            }
+            winit::event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                controler.resize(*new_inner_size);
+            }
+            winit::event::WindowEvent::MouseInput { state, button, .. } => {
+                camera_control.process_mouse_button(button, state);
+            }
+            winit::event::WindowEvent::CursorMoved { position, .. } => {
+                camera_control.process_cursor_moved((position.x, position.y));
+            }
+            winit::event::WindowEvent::MouseWheel { delta, .. } => {
+                camera_control.process_scroll(delta);
+            }
             _ => {}
         },
         winit::event::Event::RedrawRequested(_) => {
             let start = Instant::now();
+            let mut camera = controler.camera();
+            let seconds = (start - last_frame).as_secs_f32();
+            last_frame = start;
+            if camera_control.update_camera(&mut camera, seconds) {
+                controler.set_camera(camera);
+            }
             controler.render();
-            // controler.update();
-            println!("Total time: {}", start.elapsed().as_millis());
+            log::debug!(
+                "Frame {}, total time: {}",
+                controler.frame_count(),
+                start.elapsed().as_millis()
+            );
         }
         winit::event::Event::RedrawEventsCleared => {
-            // controler.update();
-            // window.request_redraw();
+            window.request_redraw();
         }
         _ => {}
     });
 }
+
+fn render_headless(settings: &Settings, scene: &Scene) {
+    let mut controler = match pollster::block_on(headless::HeadlessControler::new(settings, scene))
+    {
+        Ok(controler) => controler,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    let start = Instant::now();
+    if systems::image_writer::is_radiance_path(&settings.output_path) {
+        let radiance = controler.render_radiance();
+        println!("Total time: {}", start.elapsed().as_millis());
+        systems::image_writer::save_radiance(
+            &settings.output_path,
+            settings.width,
+            settings.height,
+            &radiance,
+        )
+        .expect("Error write output image");
+    } else {
+        let pixels = controler.render();
+        println!("Total time: {}", start.elapsed().as_millis());
+        systems::image_writer::save_image(
+            &settings.output_path,
+            settings.width,
+            settings.height,
+            &pixels,
+        )
+        .expect("Error write output image");
+    }
+    println!("Saved {}", settings.output_path.display());
+
+    if settings.aov {
+        for (aov, pixels) in controler.aovs() {
+            let path = systems::image_writer::aov_path(&settings.output_path, aov.name());
+            systems::image_writer::save_radiance(&path, settings.width, settings.height, &pixels)
+                .expect("Error write AOV image");
+            println!("Saved {}", path.display());
+        }
+    }
+}
+
+/// Renders the generated benchmark scene headless, once through the BVH and
+/// once testing every entity, and prints both timings.
+fn run_benchmark(settings: &Settings, sphere_count: u32) {
+    let scene = systems::generator::generate_benchmark_scene(sphere_count);
+    let primitive_count = scene.spheres.len() + scene.panels.len() + scene.lights.len();
+
+    let start = Instant::now();
+    let bvh = systems::bvh::build_bvh(
+        &scene.spheres,
+        &scene.panels,
+        &scene.lights,
+        &Default::default(),
+        &mut [],
+    );
+    println!(
+        "{} primitives, BVH with {} nodes built in {} ms",
+        primitive_count,
+        bvh.nodes.len(),
+        start.elapsed().as_millis()
+    );
+
+    let mut timings = Vec::new();
+    for use_bvh in [true, false] {
+        let settings = Settings {
+            use_bvh,
+            ..settings.clone()
+        };
+        let mut controler =
+            match pollster::block_on(headless::HeadlessControler::new(&settings, &scene)) {
+                Ok(controler) => controler,
+                Err(message) => {
+                    eprintln!("{}", message);
+                    std::process::exit(1);
+                }
+            };
+        // the first frame also pays for pipeline creation
+        controler.render();
+        let start = Instant::now();
+        controler.render();
+        let millis = start.elapsed().as_secs_f64() * 1000.0;
+        println!(
+            "{}: {:.1} ms",
+            if use_bvh { "BVH" } else { "Linear" },
+            millis
+        );
+        timings.push(millis);
+    }
+    println!("Speedup: {:.2}x", timings[1] / timings[0]);
+}
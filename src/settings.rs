@@ -1,8 +1,322 @@
+use std::{path::PathBuf, str::FromStr};
+
 pub const TEXTURE_WIDTH: u32 = 512;
 pub const TEXTURE_HEIGHT: u32 = 512;
-pub const WINDOW_WIDHT: u32 = TEXTURE_WIDTH * 1;
-pub const WINDOW_HEIGHT: u32 = TEXTURE_HEIGHT * 1;
 pub const WINDOW_TITLE: &str = "TEST";
-pub const WINDOW_TOTAL_PIXEL: u64 = (TEXTURE_WIDTH * TEXTURE_HEIGHT) as u64;
-// pub const IMAGE_PATH: &str = "test.png";
+pub const IMAGE_PATH: &str = "render.png";
 pub const SAMPLES_PER_PIXEL: u64 = 10;
+pub const MAX_DEPTH: u32 = 200;
+pub const RUSSIAN_ROULETTE_DEPTH: u32 = 3;
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+pub const USAGE: &str = "Usage: ray-tracing-wgpu [OPTIONS]
+
+Options:
+    -w, --width <PIXELS>     Image and initial window width [default: 512]
+    -h, --height <PIXELS>    Image and initial window height [default: 512]
+        --render-scale <FACTOR>
+                             Trace at FACTOR times the image size, 0.25 to 2 [default: 1]
+        --filter <FILTER>    Scaling to the image size, nearest or linear [default: linear]
+        --tonemap <OPERATOR> Display transform, linear, reinhard, aces or agx [default: linear]
+        --exposure <STOPS>   Brightens the radiance by 2^STOPS before tonemapping [default: 0]
+        --denoise <MODE>     Edge-aware wavelet denoiser, off, spatial or temporal [default: off]
+    -s, --spp <COUNT>        Samples per pixel for each frame [default: 10]
+    -d, --max-depth <COUNT>  Maximum bounce depth [default: 200]
+        --rr-depth <COUNT>   Bounces before Russian roulette may end a path [default: 3]
+        --seed <NUMBER>      Seed for the random number generator [default: 0]
+        --sampler <KIND>     Sample sequence, independent, stratified or sobol [default: sobol]
+        --scene <FILE>       Scene description file
+    -o, --output <FILE>      Output image, .png, .ppm, or linear .exr or .hdr
+                             [default: render.png]
+        --headless           Render one frame to the output file without a window
+        --aov                Also write first-hit albedo, normal, depth, position, material
+                             and object index, to FILE.<name>.exr headless or to view with V
+        --fallback           Use the fallback (software) adapter, headless only
+        --no-bvh             Test every entity for every ray instead of using a BVH
+        --benchmark <COUNT>  Time headless renders of COUNT spheres with and without the BVH
+        --help               Print this message";
+
+/// Render parameters chosen at startup, defaulting to the constants above.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    pub spp: u32,
+    pub max_depth: u32,
+    pub russian_roulette_depth: u32,
+    pub seed: u32,
+    pub scene_path: Option<PathBuf>,
+    pub output_path: PathBuf,
+    pub headless: bool,
+    /// Whether the first-hit AOVs are written, see `systems::aov`.
+    pub aov: bool,
+    pub force_fallback_adapter: bool,
+    pub use_bvh: bool,
+    /// Sphere count of the generated benchmark scene.
+    pub benchmark: Option<u32>,
+    /// Traced pixels per image pixel along each axis.
+    pub render_scale: f32,
+    pub scale_filter: ScaleFilter,
+    pub tonemap: Tonemap,
+    /// Stops applied to the radiance before the tonemap operator.
+    pub exposure: f32,
+    pub denoise: Denoise,
+    pub sampler: Sampler,
+}
+
+/// How a trace at another resolution is scaled to the image or window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleFilter {
+    Nearest,
+    /// Bilinear when enlarging, averaging when shrinking.
+    Linear,
+}
+
+impl FromStr for ScaleFilter {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "nearest" => Ok(ScaleFilter::Nearest),
+            "linear" => Ok(ScaleFilter::Linear),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Operator mapping linear radiance into the display range, before the sRGB
+/// transfer function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemap {
+    /// Clamps each channel to 1.
+    Linear,
+    /// `x / (1 + x)` on the luminance, keeping the hue.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference and output transforms.
+    Aces,
+    /// Troy Sobotka's AgX base look, as fitted by Benjamin Wrensch.
+    Agx,
+}
+
+impl Tonemap {
+    pub const ALL: [Tonemap; 4] = [
+        Tonemap::Linear,
+        Tonemap::Reinhard,
+        Tonemap::Aces,
+        Tonemap::Agx,
+    ];
+
+    /// The operator after this one, wrapping around.
+    pub fn next(self) -> Self {
+        Tonemap::ALL[(self as usize + 1) % Tonemap::ALL.len()]
+    }
+}
+
+impl FromStr for Tonemap {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "linear" => Ok(Tonemap::Linear),
+            "reinhard" => Ok(Tonemap::Reinhard),
+            "aces" => Ok(Tonemap::Aces),
+            "agx" => Ok(Tonemap::Agx),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the radiance is filtered before tonemapping, see `denoiser`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denoise {
+    Off,
+    /// Within each frame only.
+    Spatial,
+    /// Also blending in the reprojected previous frame after camera motion.
+    Temporal,
+}
+
+impl Denoise {
+    /// The mode after this one, wrapping around.
+    pub fn next(self) -> Self {
+        match self {
+            Denoise::Off => Denoise::Spatial,
+            Denoise::Spatial => Denoise::Temporal,
+            Denoise::Temporal => Denoise::Off,
+        }
+    }
+}
+
+impl FromStr for Denoise {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(Denoise::Off),
+            "spatial" => Ok(Denoise::Spatial),
+            "temporal" => Ok(Denoise::Temporal),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Sequence the path tracer draws its random numbers from, keyed by pixel,
+/// sample index and dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampler {
+    /// Hashed uniform numbers, with no structure between samples.
+    Independent,
+    /// One jittered cell of a grid per sample, within each frame.
+    Stratified,
+    /// Owen-scrambled Sobol points, progressive across frames.
+    Sobol,
+}
+
+impl FromStr for Sampler {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "independent" => Ok(Sampler::Independent),
+            "stratified" => Ok(Sampler::Stratified),
+            "sobol" => Ok(Sampler::Sobol),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Why command-line arguments gave no settings.
+#[derive(Debug, Clone)]
+pub enum ArgsError {
+    /// `--help` was given, the usage goes to stdout.
+    Help,
+    /// A message ready to print, including usage where it helps.
+    Invalid(String),
+}
+
+impl From<String> for ArgsError {
+    fn from(message: String) -> Self {
+        ArgsError::Invalid(message)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: TEXTURE_WIDTH,
+            height: TEXTURE_HEIGHT,
+            spp: SAMPLES_PER_PIXEL as u32,
+            max_depth: MAX_DEPTH,
+            russian_roulette_depth: RUSSIAN_ROULETTE_DEPTH,
+            seed: 0,
+            scene_path: None,
+            output_path: PathBuf::from(IMAGE_PATH),
+            headless: false,
+            aov: false,
+            force_fallback_adapter: false,
+            use_bvh: true,
+            benchmark: None,
+            render_scale: 1.0,
+            scale_filter: ScaleFilter::Linear,
+            tonemap: Tonemap::Linear,
+            exposure: 0.0,
+            denoise: Denoise::Off,
+            sampler: Sampler::Sobol,
+        }
+    }
+}
+
+impl Settings {
+    /// Parses command-line arguments, without the program name.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut settings = Settings::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-w" | "--width" => settings.width = parse_value(&arg, args.next())?,
+                "-h" | "--height" => settings.height = parse_value(&arg, args.next())?,
+                "-s" | "--spp" => settings.spp = parse_value(&arg, args.next())?,
+                "-d" | "--max-depth" => settings.max_depth = parse_value(&arg, args.next())?,
+                "--rr-depth" => settings.russian_roulette_depth = parse_value(&arg, args.next())?,
+                "--seed" => settings.seed = parse_value(&arg, args.next())?,
+                "--scene" => settings.scene_path = Some(parse_value(&arg, args.next())?),
+                "-o" | "--output" => settings.output_path = parse_value(&arg, args.next())?,
+                "--headless" => settings.headless = true,
+                "--aov" => settings.aov = true,
+                "--fallback" => settings.force_fallback_adapter = true,
+                "--no-bvh" => settings.use_bvh = false,
+                "--benchmark" => settings.benchmark = Some(parse_value(&arg, args.next())?),
+                "--render-scale" => settings.render_scale = parse_value(&arg, args.next())?,
+                "--filter" => settings.scale_filter = parse_value(&arg, args.next())?,
+                "--tonemap" => settings.tonemap = parse_value(&arg, args.next())?,
+                "--exposure" => settings.exposure = parse_value(&arg, args.next())?,
+                "--denoise" => settings.denoise = parse_value(&arg, args.next())?,
+                "--sampler" => settings.sampler = parse_value(&arg, args.next())?,
+                "--help" => return Err(ArgsError::Help),
+                _ => {
+                    return Err(ArgsError::Invalid(format!(
+                        "Unknown argument `{}`\n\n{}",
+                        arg, USAGE
+                    )))
+                }
+            }
+        }
+
+        if settings.width == 0 || settings.height == 0 {
+            return Err(ArgsError::Invalid(
+                "Width and height must be greater than 0".to_string(),
+            ));
+        }
+        if settings.spp == 0 {
+            return Err(ArgsError::Invalid(
+                "Samples per pixel must be greater than 0".to_string(),
+            ));
+        }
+        if settings.max_depth == 0 {
+            return Err(ArgsError::Invalid(
+                "Max depth must be greater than 0".to_string(),
+            ));
+        }
+        if settings.benchmark == Some(0) {
+            return Err(ArgsError::Invalid(
+                "Benchmark sphere count must be greater than 0".to_string(),
+            ));
+        }
+        if !settings.exposure.is_finite() {
+            return Err(ArgsError::Invalid(
+                "Exposure must be a finite number of stops".to_string(),
+            ));
+        }
+        if !(MIN_RENDER_SCALE..=MAX_RENDER_SCALE).contains(&settings.render_scale) {
+            return Err(ArgsError::Invalid(format!(
+                "Render scale must be between {} and {}",
+                MIN_RENDER_SCALE, MAX_RENDER_SCALE
+            )));
+        }
+
+        Ok(settings)
+    }
+
+    /// Whether the AOVs are written, asked for or to guide the denoiser.
+    pub fn aov_enabled(&self) -> bool {
+        self.aov || self.denoise != Denoise::Off
+    }
+
+    pub fn total_pixel(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// Resolution to trace at for an image or window of `size`.
+    pub fn trace_size(&self, size: (u32, u32)) -> (u32, u32) {
+        let scale = |pixels: u32| ((pixels as f32 * self.render_scale).round() as u32).max(1);
+        (scale(size.0), scale(size.1))
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Missing value for `{}`", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value `{}` for `{}`", value, flag))
+}
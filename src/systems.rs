@@ -1 +1,11 @@
+pub mod aov;
+pub mod bvh;
+pub mod camera_control;
+pub mod emitters;
+pub mod environment;
 pub mod generator;
+pub mod image_writer;
+pub mod math;
+pub mod obj_loader;
+pub mod scene;
+pub mod transform;
@@ -0,0 +1,91 @@
+/// `vec4<f32>` entries per pixel in the AOV buffer:
+///
+/// - albedo rgb, linear depth,
+/// - shading normal facing the camera, material index,
+/// - world position, object index.
+///
+/// Misses have a zero albedo, normal and depth and both indices at -1.
+pub const AOV_STRIDE: usize = 3;
+
+/// First-hit quantity of the ray through each pixel center, written next to
+/// the beauty image when AOVs are enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    /// Distance along the camera's viewing direction.
+    Depth,
+    Position,
+    /// Index into the scene's material list.
+    Material,
+    /// Spheres, panels, lights, then mesh instances, counted in one range.
+    Object,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::Material,
+        Aov::Object,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Material => "material",
+            Aov::Object => "object",
+        }
+    }
+
+    /// Pulls this AOV out of the interleaved buffer as one rgba value per
+    /// pixel, scalars repeated over rgb.
+    pub fn extract(self, data: &[[f32; 4]]) -> Vec<[f32; 4]> {
+        data.chunks_exact(AOV_STRIDE)
+            .map(|pixel| {
+                let scalar = |value: f32| [value, value, value, 1.0];
+                match self {
+                    Aov::Albedo => [pixel[0][0], pixel[0][1], pixel[0][2], 1.0],
+                    Aov::Normal => [pixel[1][0], pixel[1][1], pixel[1][2], 1.0],
+                    Aov::Depth => scalar(pixel[0][3]),
+                    Aov::Position => [pixel[2][0], pixel[2][1], pixel[2][2], 1.0],
+                    Aov::Material => scalar(pixel[1][3]),
+                    Aov::Object => scalar(pixel[2][3]),
+                }
+            })
+            .collect()
+    }
+}
+
+/// What the window shows: the tonemapped image or one AOV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Beauty,
+    Aov(Aov),
+}
+
+impl View {
+    /// The view after this one, wrapping around to the beauty image.
+    pub fn next(self) -> Self {
+        match self {
+            View::Beauty => View::Aov(Aov::ALL[0]),
+            View::Aov(aov) => match Aov::ALL.iter().position(|other| *other == aov) {
+                Some(index) if index + 1 < Aov::ALL.len() => View::Aov(Aov::ALL[index + 1]),
+                _ => View::Beauty,
+            },
+        }
+    }
+
+    /// Index the tonemap shader switches on, 0 for the beauty image.
+    pub fn index(self) -> u32 {
+        match self {
+            View::Beauty => 0,
+            View::Aov(aov) => aov as u32 + 1,
+        }
+    }
+}
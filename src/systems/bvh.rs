@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::entity::{Instance, Mesh, Panel, Sphere};
+
+/// Primitive kinds, stored in the top two bits of each primitive reference.
+/// The top level hierarchy holds instances where the mesh ones hold
+/// triangles, so those two share a kind.
+pub const PRIMITIVE_SPHERE: u32 = 0;
+pub const PRIMITIVE_PANEL: u32 = 1;
+pub const PRIMITIVE_TRIANGLE: u32 = 2;
+pub const PRIMITIVE_INSTANCE: u32 = 2;
+pub const PRIMITIVE_LIGHT: u32 = 3;
+const PRIMITIVE_INDEX_MASK: u32 = (1 << 30) - 1;
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+/// Entries of each traversal stack in the compute shader, which the tracer
+/// defines from this. Walking down a tree pushes at most one node per level
+/// below the root, so every tree must be at most this deep.
+pub const BVH_STACK_SIZE: u32 = 32;
+/// Deepest level subdivision goes to, with the root at 0, a little short of
+/// what the traversal stack holds.
+const MAX_DEPTH: u32 = BVH_STACK_SIZE - 2;
+/// Flat panels still need some thickness for the slab test.
+const MIN_EXTENT: f32 = 0.001;
+
+/// Interior nodes have `count == 0` and their children at `left_or_first`
+/// and `left_or_first + 1`; leaves reference `count` primitives starting at
+/// `left_or_first`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BvhNode {
+    pub aabb_min: [f32; 3],
+    pub left_or_first: u32,
+    pub aabb_max: [f32; 3],
+    pub count: u32,
+}
+
+unsafe impl Zeroable for BvhNode {}
+unsafe impl Pod for BvhNode {}
+
+/// The top level hierarchy starts at node 0, the one of each mesh at the
+/// `bvh_root` of its instances.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// `kind << 30 | index` into the list of that primitive kind.
+    pub primitives: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Aabb {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Aabb {
+    fn empty() -> Self {
+        Aabb {
+            min: [f32::MAX; 3],
+            max: [f32::MIN; 3],
+        }
+    }
+
+    fn grow_point(&mut self, point: [f32; 3]) {
+        for (axis, value) in point.into_iter().enumerate() {
+            self.min[axis] = self.min[axis].min(value);
+            self.max[axis] = self.max[axis].max(value);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min[0] > self.max[0]
+    }
+
+    /// Grows to enclose `other`; an empty box leaves this one unchanged.
+    fn grow(&mut self, other: &Aabb) {
+        if other.is_empty() {
+            return;
+        }
+        self.grow_point(other.min);
+        self.grow_point(other.max);
+    }
+
+    fn pad(mut self) -> Self {
+        for axis in 0..3 {
+            if self.max[axis] - self.min[axis] < MIN_EXTENT {
+                self.min[axis] -= MIN_EXTENT;
+                self.max[axis] += MIN_EXTENT;
+            }
+        }
+        self
+    }
+
+    fn centroid(&self) -> [f32; 3] {
+        [
+            (self.min[0] + self.max[0]) * 0.5,
+            (self.min[1] + self.max[1]) * 0.5,
+            (self.min[2] + self.max[2]) * 0.5,
+        ]
+    }
+
+    fn half_area(&self) -> f32 {
+        let extent = [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        ];
+        if extent.iter().any(|e| *e < 0.0) {
+            return 0.0;
+        }
+        extent[0] * extent[1] + extent[1] * extent[2] + extent[2] * extent[0]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BuildPrimitive {
+    bounds: Aabb,
+    centroid: [f32; 3],
+    reference: u32,
+}
+
+impl BuildPrimitive {
+    fn new(kind: u32, index: usize, bounds: Aabb) -> Self {
+        let bounds = bounds.pad();
+        BuildPrimitive {
+            bounds,
+            centroid: bounds.centroid(),
+            reference: kind << 30 | (index as u32 & PRIMITIVE_INDEX_MASK),
+        }
+    }
+}
+
+/// Builds a binned SAH hierarchy over every sphere, panel, light and mesh
+/// instance of the scene, and one over the triangles of each mesh, which
+/// is stored in the instances placing it.
+pub fn build_bvh(
+    spheres: &[Sphere],
+    panels: &[Panel],
+    lights: &[Panel],
+    mesh: &Mesh,
+    instances: &mut [Instance],
+) -> Bvh {
+    let mut primitives = Vec::new();
+    for (index, sphere) in spheres.iter().enumerate() {
+        let mut bounds = Aabb::empty();
+        for sign in [-1.0, 1.0] {
+            bounds.grow_point([
+                sphere.position[0] + sign * sphere.radius,
+                sphere.position[1] + sign * sphere.radius,
+                sphere.position[2] + sign * sphere.radius,
+            ]);
+        }
+        primitives.push(BuildPrimitive::new(PRIMITIVE_SPHERE, index, bounds));
+    }
+    for (kind, list) in [(PRIMITIVE_PANEL, panels), (PRIMITIVE_LIGHT, lights)] {
+        for (index, panel) in list.iter().enumerate() {
+            let mut bounds = Aabb::empty();
+            for corner in panel.corners() {
+                bounds.grow_point(corner);
+            }
+            primitives.push(BuildPrimitive::new(kind, index, bounds));
+        }
+    }
+    for (index, instance) in instances.iter().enumerate() {
+        let mut object_bounds = Aabb::empty();
+        for triangle in triangle_range(instance) {
+            object_bounds.grow(&triangle_bounds(mesh, triangle));
+        }
+        // the world box around the corners of the object one
+        let mut bounds = Aabb::empty();
+        for corner in 0..8 {
+            let select = |axis: usize| {
+                if corner >> axis & 1 == 0 {
+                    object_bounds.min[axis]
+                } else {
+                    object_bounds.max[axis]
+                }
+            };
+            bounds.grow_point(instance.point_to_world([select(0), select(1), select(2), 1.0]));
+        }
+        primitives.push(BuildPrimitive::new(PRIMITIVE_INSTANCE, index, bounds));
+    }
+
+    let mut bvh = Bvh::default();
+    if primitives.is_empty() {
+        return bvh;
+    }
+    append_tree(&mut bvh, primitives);
+
+    // instances of the same mesh share its hierarchy
+    let mut roots = HashMap::new();
+    for instance in instances.iter_mut() {
+        instance.bvh_root = *roots.entry(instance.first_triangle).or_insert_with(|| {
+            let triangles = triangle_range(instance)
+                .map(|triangle| {
+                    let bounds = triangle_bounds(mesh, triangle);
+                    BuildPrimitive::new(PRIMITIVE_TRIANGLE, triangle, bounds)
+                })
+                .collect();
+            append_tree(&mut bvh, triangles)
+        });
+    }
+
+    bvh
+}
+
+fn triangle_range(instance: &Instance) -> std::ops::Range<usize> {
+    let first = instance.first_triangle as usize;
+    first..first + instance.triangle_count as usize
+}
+
+fn triangle_bounds(mesh: &Mesh, triangle: usize) -> Aabb {
+    let mut bounds = Aabb::empty();
+    for vertex_index in mesh.triangles[triangle].indices {
+        let position = mesh.vertices[vertex_index as usize].position;
+        bounds.grow_point([position[0], position[1], position[2]]);
+    }
+    bounds
+}
+
+/// Adds a hierarchy over `primitives` to the end of `bvh` and returns its
+/// root node.
+fn append_tree(bvh: &mut Bvh, mut primitives: Vec<BuildPrimitive>) -> u32 {
+    let root = bvh.nodes.len();
+    let primitive_base = bvh.primitives.len() as u32;
+    bvh.nodes.push(BvhNode {
+        aabb_min: [0.0; 3],
+        left_or_first: 0,
+        aabb_max: [0.0; 3],
+        count: primitives.len() as u32,
+    });
+    let depth = subdivide(&mut bvh.nodes, &mut primitives, root, 0);
+    assert!(
+        depth < BVH_STACK_SIZE,
+        "BVH depth {} overflows the traversal stack",
+        depth
+    );
+
+    // leaves were built against `primitives` alone
+    for node in bvh.nodes[root..].iter_mut().filter(|node| node.count > 0) {
+        node.left_or_first += primitive_base;
+    }
+    bvh.primitives
+        .extend(primitives.iter().map(|primitive| primitive.reference));
+
+    root as u32
+}
+
+/// Splits the node until its leaves are small enough and returns the
+/// deepest level below it.
+fn subdivide(
+    nodes: &mut Vec<BvhNode>,
+    primitives: &mut [BuildPrimitive],
+    node_index: usize,
+    depth: u32,
+) -> u32 {
+    let first = nodes[node_index].left_or_first as usize;
+    let count = nodes[node_index].count as usize;
+    let node_primitives = &mut primitives[first..first + count];
+
+    let mut bounds = Aabb::empty();
+    let mut centroid_bounds = Aabb::empty();
+    for primitive in node_primitives.iter() {
+        bounds.grow(&primitive.bounds);
+        centroid_bounds.grow_point(primitive.centroid);
+    }
+    nodes[node_index].aabb_min = bounds.min;
+    nodes[node_index].aabb_max = bounds.max;
+
+    if count <= 1 || depth >= MAX_DEPTH {
+        return depth;
+    }
+
+    let (axis, split, split_cost) = match find_split(node_primitives, &centroid_bounds) {
+        Some(split) => split,
+        None => return depth,
+    };
+    let leaf_cost = count as f32 * bounds.half_area();
+    if split_cost >= leaf_cost && count <= MAX_LEAF_SIZE {
+        return depth;
+    }
+
+    // partition in place around the chosen plane
+    let mut left_count = 0;
+    for index in 0..count {
+        if node_primitives[index].centroid[axis] < split {
+            node_primitives.swap(index, left_count);
+            left_count += 1;
+        }
+    }
+    if left_count == 0 || left_count == count {
+        return depth;
+    }
+
+    let left_index = nodes.len();
+    nodes.push(BvhNode {
+        aabb_min: [0.0; 3],
+        left_or_first: first as u32,
+        aabb_max: [0.0; 3],
+        count: left_count as u32,
+    });
+    nodes.push(BvhNode {
+        aabb_min: [0.0; 3],
+        left_or_first: (first + left_count) as u32,
+        aabb_max: [0.0; 3],
+        count: (count - left_count) as u32,
+    });
+    nodes[node_index].left_or_first = left_index as u32;
+    nodes[node_index].count = 0;
+
+    let left_depth = subdivide(nodes, primitives, left_index, depth + 1);
+    let right_depth = subdivide(nodes, primitives, left_index + 1, depth + 1);
+    left_depth.max(right_depth)
+}
+
+/// Returns the axis, plane position and SAH cost of the cheapest split
+/// between equally sized centroid bins.
+fn find_split(primitives: &[BuildPrimitive], centroid_bounds: &Aabb) -> Option<(usize, f32, f32)> {
+    let mut best: Option<(usize, f32, f32)> = None;
+    for axis in 0..3 {
+        let min = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bin_bounds = [Aabb::empty(); BIN_COUNT];
+        let mut bin_counts = [0usize; BIN_COUNT];
+        let bin_scale = BIN_COUNT as f32 / extent;
+        for primitive in primitives {
+            let bin = (((primitive.centroid[axis] - min) * bin_scale) as usize).min(BIN_COUNT - 1);
+            bin_counts[bin] += 1;
+            bin_bounds[bin].grow(&primitive.bounds);
+        }
+
+        // sweep from both sides to get the cost of every plane
+        let mut left_areas = [0.0f32; BIN_COUNT - 1];
+        let mut left_counts = [0usize; BIN_COUNT - 1];
+        let mut left_box = Aabb::empty();
+        let mut left_sum = 0;
+        for plane in 0..BIN_COUNT - 1 {
+            left_sum += bin_counts[plane];
+            left_box.grow(&bin_bounds[plane]);
+            left_counts[plane] = left_sum;
+            left_areas[plane] = left_box.half_area();
+        }
+        let mut right_box = Aabb::empty();
+        let mut right_sum = 0;
+        for plane in (0..BIN_COUNT - 1).rev() {
+            right_sum += bin_counts[plane + 1];
+            right_box.grow(&bin_bounds[plane + 1]);
+            // a plane with nothing on one side does not split
+            if left_counts[plane] == 0 || right_sum == 0 {
+                continue;
+            }
+            let cost = left_counts[plane] as f32 * left_areas[plane]
+                + right_sum as f32 * right_box.half_area();
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                let split = min + extent * (plane + 1) as f32 / BIN_COUNT as f32;
+                best = Some((axis, split, cost));
+            }
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{Triangle, Vertex};
+    use crate::systems::transform::Transform;
+
+    fn sphere_primitives(xs: &[f32]) -> Vec<BuildPrimitive> {
+        xs.iter()
+            .enumerate()
+            .map(|(index, x)| {
+                let mut bounds = Aabb::empty();
+                bounds.grow_point([x - 0.1, -0.1, -0.1]);
+                bounds.grow_point([x + 0.1, 0.1, 0.1]);
+                BuildPrimitive::new(PRIMITIVE_SPHERE, index, bounds)
+            })
+            .collect()
+    }
+
+    /// Deepest level below `node`, with `node` at 0.
+    fn tree_depth(bvh: &Bvh, node: usize) -> u32 {
+        let node = bvh.nodes[node];
+        if node.count > 0 {
+            return 0;
+        }
+        let left = node.left_or_first as usize;
+        1 + tree_depth(bvh, left).max(tree_depth(bvh, left + 1))
+    }
+
+    fn node_bounds(node: &BvhNode) -> Aabb {
+        Aabb {
+            min: node.aabb_min,
+            max: node.aabb_max,
+        }
+    }
+
+    /// Whether `point` lies within `bounds`, give or take rounding.
+    fn contains(bounds: &Aabb, point: [f32; 3]) -> bool {
+        (0..3).all(|axis| {
+            bounds.min[axis] - 1e-4 <= point[axis] && point[axis] <= bounds.max[axis] + 1e-4
+        })
+    }
+
+    /// Reference and leaf box of every primitive below `node`, checking on
+    /// the way down that children lie within their parent.
+    fn leaves(bvh: &Bvh, node: usize, found: &mut Vec<(u32, Aabb)>) {
+        let node = bvh.nodes[node];
+        let bounds = node_bounds(&node);
+        let first = node.left_or_first as usize;
+        if node.count > 0 {
+            for reference in &bvh.primitives[first..first + node.count as usize] {
+                found.push((*reference, bounds));
+            }
+            return;
+        }
+        for child in [first, first + 1] {
+            let child_bounds = node_bounds(&bvh.nodes[child]);
+            assert!(contains(&bounds, child_bounds.min) && contains(&bounds, child_bounds.max));
+            leaves(bvh, child, found);
+        }
+    }
+
+    #[test]
+    fn leaves_enclose_their_primitives() {
+        let spheres: Vec<Sphere> = (0..40)
+            .map(|index| {
+                let position = [
+                    (index * 37 % 11) as f32,
+                    (index * 13 % 7) as f32,
+                    index as f32,
+                ];
+                Sphere::new(position, 0, 0.1 + (index % 5) as f32 * 0.3)
+            })
+            .collect();
+        let panels: Vec<Panel> = (0..10)
+            .map(|index| {
+                let origin = [index as f32 * 2.0, -3.0, (index * 7 % 10) as f32];
+                Panel::new(origin, [1.0, 0.0, 0.5], [0.0, 0.0, 2.0], 0)
+            })
+            .collect();
+        let lights = [Panel::new(
+            [0.0, 8.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+            0,
+        )];
+        let mut mesh = Mesh::default();
+        for index in 0..20 {
+            let x = index as f32;
+            mesh.vertices
+                .push(Vertex::new([x, 0.0, 0.0], [0.0, 0.0, 1.0]));
+            mesh.vertices
+                .push(Vertex::new([x + 0.5, 1.0, x * 0.2], [0.0, 0.0, 1.0]));
+            mesh.vertices
+                .push(Vertex::new([x + 1.0, 0.0, 0.0], [0.0, 0.0, 1.0]));
+            let first = index * 3;
+            mesh.triangles
+                .push(Triangle::new([first, first + 1, first + 2], 0));
+        }
+        let transform =
+            Transform::translation([5.0, 5.0, 5.0]) * Transform::rotation([0.0, 45.0, 30.0]);
+        let mut instances = [
+            Instance::new(&transform, 0, 20),
+            Instance::new(&Transform::scaling([0.5, 2.0, 1.0]), 0, 20),
+        ];
+        let bvh = build_bvh(&spheres, &panels, &lights, &mesh, &mut instances);
+
+        let triangle_points = |triangle: usize| {
+            mesh.triangles[triangle].indices.map(|index| {
+                let position = mesh.vertices[index as usize].position;
+                [position[0], position[1], position[2]]
+            })
+        };
+        let mut found = Vec::new();
+        leaves(&bvh, 0, &mut found);
+        assert_eq!(
+            found.len(),
+            spheres.len() + panels.len() + lights.len() + instances.len()
+        );
+        for (reference, bounds) in found {
+            let index = (reference & PRIMITIVE_INDEX_MASK) as usize;
+            let points = match reference >> 30 {
+                PRIMITIVE_SPHERE => {
+                    let sphere = spheres[index];
+                    [-1.0, 1.0]
+                        .map(|sign| {
+                            [0, 1, 2].map(|axis| sphere.position[axis] + sign * sphere.radius)
+                        })
+                        .to_vec()
+                }
+                PRIMITIVE_PANEL => panels[index].corners().to_vec(),
+                PRIMITIVE_LIGHT => lights[index].corners().to_vec(),
+                _ => {
+                    let instance = &instances[index];
+                    triangle_range(instance)
+                        .flat_map(triangle_points)
+                        .map(|[x, y, z]| instance.point_to_world([x, y, z, 1.0]))
+                        .collect()
+                }
+            };
+            for point in points {
+                assert!(contains(&bounds, point), "{:?} outside {:?}", point, bounds);
+            }
+        }
+
+        // both instances share the hierarchy over the triangles
+        assert_eq!(instances[0].bvh_root, instances[1].bvh_root);
+        let mut found = Vec::new();
+        leaves(&bvh, instances[0].bvh_root as usize, &mut found);
+        assert_eq!(found.len(), mesh.triangles.len());
+        for (reference, bounds) in found {
+            assert_eq!(reference >> 30, PRIMITIVE_TRIANGLE);
+            for point in triangle_points((reference & PRIMITIVE_INDEX_MASK) as usize) {
+                assert!(contains(&bounds, point), "{:?} outside {:?}", point, bounds);
+            }
+        }
+    }
+
+    #[test]
+    fn deep_trees_fit_the_traversal_stack() {
+        // every split peels off a single sphere, the worst case for depth
+        let spheres: Vec<Sphere> = (0..100)
+            .map(|index| Sphere::new([1.5f32.powi(index), 0.0, 0.0], 0, 0.1))
+            .collect();
+        let bvh = build_bvh(&spheres, &[], &[], &Mesh::default(), &mut []);
+        let depth = tree_depth(&bvh, 0);
+        assert!(depth <= MAX_DEPTH);
+        assert!(depth < BVH_STACK_SIZE);
+    }
+
+    #[test]
+    fn grow_ignores_empty_boxes() {
+        let mut bounds = Aabb::empty();
+        bounds.grow_point([0.0, 0.0, 0.0]);
+        bounds.grow_point([1.0, 2.0, 3.0]);
+        bounds.grow(&Aabb::empty());
+        assert_eq!(bounds.min, [0.0, 0.0, 0.0]);
+        assert_eq!(bounds.max, [1.0, 2.0, 3.0]);
+        assert_eq!(bounds.half_area(), 11.0);
+    }
+
+    #[test]
+    fn split_falls_into_the_gap() {
+        // two clusters leave most of the bins in between empty
+        let primitives = sphere_primitives(&[0.0, 0.5, 1.0, 10.0, 10.5, 11.0]);
+        let mut centroid_bounds = Aabb::empty();
+        for primitive in &primitives {
+            centroid_bounds.grow_point(primitive.centroid);
+        }
+
+        let (axis, split, cost) = find_split(&primitives, &centroid_bounds).unwrap();
+        assert_eq!(axis, 0);
+        assert!(split > 1.0 && split < 10.0, "split at {}", split);
+        assert!(cost.is_finite());
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+use crate::entity::Camera;
+
+/// Fraction of the eye-target distance travelled per second while a
+/// movement key is held.
+const MOVE_SPEED: f32 = 0.5;
+/// Radians of orbit per pixel of mouse drag.
+const ORBIT_SPEED: f32 = 0.005;
+/// Distance factor per scroll line.
+const ZOOM_STEP: f32 = 0.9;
+const MIN_DISTANCE: f32 = 1.0;
+
+/// Tracks keyboard and mouse state between frames: WASD/QE fly the camera,
+/// dragging with the left button orbits around the target and scrolling
+/// zooms towards it.
+#[derive(Debug, Default)]
+pub struct CameraControl {
+    pressed_keys: HashSet<VirtualKeyCode>,
+    dragging: bool,
+    cursor_position: Option<(f64, f64)>,
+    drag_delta: (f64, f64),
+    scroll_lines: f32,
+}
+
+impl CameraControl {
+    /// Returns true when the key is one of the movement keys.
+    pub fn process_keyboard(&mut self, keycode: VirtualKeyCode, state: ElementState) -> bool {
+        match keycode {
+            VirtualKeyCode::W
+            | VirtualKeyCode::A
+            | VirtualKeyCode::S
+            | VirtualKeyCode::D
+            | VirtualKeyCode::Q
+            | VirtualKeyCode::E => {
+                match state {
+                    ElementState::Pressed => self.pressed_keys.insert(keycode),
+                    ElementState::Released => self.pressed_keys.remove(&keycode),
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        if button == MouseButton::Left {
+            self.dragging = state == ElementState::Pressed;
+        }
+    }
+
+    pub fn process_cursor_moved(&mut self, position: (f64, f64)) {
+        if let (true, Some(last)) = (self.dragging, self.cursor_position) {
+            self.drag_delta.0 += position.0 - last.0;
+            self.drag_delta.1 += position.1 - last.1;
+        }
+        self.cursor_position = Some(position);
+    }
+
+    pub fn process_scroll(&mut self, delta: MouseScrollDelta) {
+        self.scroll_lines += match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 40.0,
+        };
+    }
+
+    /// Applies the input gathered since the last call. Returns true if the
+    /// camera changed and has to be uploaded again.
+    pub fn update_camera(&mut self, camera: &mut Camera, seconds: f32) -> bool {
+        let mut changed = false;
+
+        let mut direction = [0.0f32; 3];
+        for key in &self.pressed_keys {
+            match key {
+                VirtualKeyCode::D => direction[0] += 1.0,
+                VirtualKeyCode::A => direction[0] -= 1.0,
+                VirtualKeyCode::E => direction[1] += 1.0,
+                VirtualKeyCode::Q => direction[1] -= 1.0,
+                VirtualKeyCode::W => direction[2] += 1.0,
+                VirtualKeyCode::S => direction[2] -= 1.0,
+                _ => {}
+            }
+        }
+        if direction != [0.0; 3] {
+            let step = camera.distance() * MOVE_SPEED * seconds;
+            camera.translate([
+                direction[0] * step,
+                direction[1] * step,
+                direction[2] * step,
+            ]);
+            changed = true;
+        }
+
+        if self.drag_delta != (0.0, 0.0) {
+            camera.orbit(
+                -self.drag_delta.0 as f32 * ORBIT_SPEED,
+                self.drag_delta.1 as f32 * ORBIT_SPEED,
+            );
+            self.drag_delta = (0.0, 0.0);
+            changed = true;
+        }
+
+        if self.scroll_lines != 0.0 {
+            let factor = ZOOM_STEP.powf(self.scroll_lines);
+            if camera.distance() * factor >= MIN_DISTANCE {
+                camera.zoom(factor);
+            }
+            self.scroll_lines = 0.0;
+            changed = true;
+        }
+
+        changed
+    }
+}
@@ -0,0 +1,103 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    entity::{Instance, Material, Mesh, Panel, Sphere},
+    systems::{
+        bvh::{PRIMITIVE_LIGHT, PRIMITIVE_PANEL, PRIMITIVE_SPHERE, PRIMITIVE_TRIANGLE},
+        math::{cross, length, sub},
+    },
+};
+
+/// Entry of the table light sampling picks from, one per emissive sphere,
+/// panel, light or triangle.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Emitter {
+    /// `kind << 30 | index`, as in `Bvh::primitives`.
+    pub primitive: u32,
+    /// Instance placing the triangle, zero for other kinds.
+    pub instance: u32,
+    /// Probability of picking this or an earlier entry.
+    pub cdf: f32,
+}
+
+unsafe impl Zeroable for Emitter {}
+unsafe impl Pod for Emitter {}
+
+/// Collects every emissive primitive with a selection CDF proportional to
+/// emitted power, area times the luminance of the emission, so that within
+/// one mesh triangles are picked by their area after the instance
+/// transform. Also returns the total power the
+/// shader divides by to get the probability of a single emitter.
+pub fn build_emitters(
+    materials: &[Material],
+    spheres: &[Sphere],
+    panels: &[Panel],
+    lights: &[Panel],
+    mesh: &Mesh,
+    instances: &[Instance],
+) -> (Vec<Emitter>, f32) {
+    let luminance = |material: u32| match materials.get(material as usize) {
+        Some(Material::Emissive { emission }) => {
+            0.2126 * emission[0] + 0.7152 * emission[1] + 0.0722 * emission[2]
+        }
+        _ => 0.0,
+    };
+
+    let mut powers = Vec::new();
+    for (index, sphere) in spheres.iter().enumerate() {
+        let area = 4.0 * std::f32::consts::PI * sphere.radius * sphere.radius;
+        powers.push((
+            PRIMITIVE_SPHERE,
+            index,
+            0,
+            area * luminance(sphere.material),
+        ));
+    }
+    for (kind, list) in [(PRIMITIVE_PANEL, panels), (PRIMITIVE_LIGHT, lights)] {
+        for (index, panel) in list.iter().enumerate() {
+            powers.push((kind, index, 0, panel.area() * luminance(panel.material)));
+        }
+    }
+    for (instance_index, instance) in instances.iter().enumerate() {
+        let first = instance.first_triangle as usize;
+        let count = instance.triangle_count as usize;
+        for (index, triangle) in mesh.triangles[first..first + count].iter().enumerate() {
+            let luminance = luminance(triangle.material);
+            if luminance <= 0.0 {
+                continue;
+            }
+            let [a, b, c] = triangle
+                .indices
+                .map(|index| instance.point_to_world(mesh.vertices[index as usize].position));
+            let area = 0.5 * length(cross(sub(b, a), sub(c, a)));
+            powers.push((
+                PRIMITIVE_TRIANGLE,
+                first + index,
+                instance_index as u32,
+                area * luminance,
+            ));
+        }
+    }
+    powers.retain(|(_, _, _, power)| *power > 0.0);
+
+    let total_power: f32 = powers.iter().map(|(_, _, _, power)| power).sum();
+    let mut sum = 0.0;
+    let mut emitters: Vec<Emitter> = powers
+        .iter()
+        .map(|(kind, index, instance, power)| {
+            sum += power;
+            Emitter {
+                primitive: kind << 30 | *index as u32,
+                instance: *instance,
+                cdf: sum / total_power,
+            }
+        })
+        .collect();
+    // rounding must not leave a gap at the top
+    if let Some(last) = emitters.last_mut() {
+        last.cdf = 1.0;
+    }
+
+    (emitters, total_power)
+}
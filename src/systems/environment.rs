@@ -0,0 +1,266 @@
+use std::path::Path;
+
+/// Widest and highest map a scene may use. Devices may allow less, which
+/// is checked once the device is known.
+pub const MAX_ENVIRONMENT_SIZE: u32 = 8192;
+/// Largest grid the sampling tables are built over. Larger maps are
+/// averaged down to it, which keeps the tables far below the storage buffer
+/// limits, while lookups still read the full resolution texture.
+const MAX_CDF_WIDTH: usize = 2048;
+const MAX_CDF_HEIGHT: usize = 1024;
+/// Entries before the marginal CDF: the integral, the grid width and height.
+const CDF_HEADER: usize = 3;
+
+/// Equirectangular radiance map lighting every ray that leaves the scene.
+/// Row 0 is straight up, the middle column looks down -z before rotation.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub width: u32,
+    pub height: u32,
+    /// Linear rgba radiance, row-major, alpha unused.
+    pub pixels: Vec<[f32; 4]>,
+    /// Multiplies every pixel.
+    pub intensity: f32,
+    /// Radians around the y axis.
+    pub rotation: f32,
+}
+
+/// Loads a Radiance `.hdr` or OpenEXR file.
+pub fn load_environment(
+    path: &Path,
+    intensity: f32,
+    rotation: f32,
+) -> Result<Environment, image::ImageError> {
+    let (width, height, pixels) = match path.extension().and_then(|ext| ext.to_str()) {
+        // `image::open` clamps Radiance files to 8 bits, the decoder keeps
+        // the float values
+        Some("hdr") => {
+            let decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(
+                std::fs::File::open(path).map_err(image::ImageError::IoError)?,
+            ))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .into_iter()
+                .map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+                .collect();
+            (metadata.width, metadata.height, pixels)
+        }
+        _ => {
+            let image = image::open(path)?.into_rgba32f();
+            let (width, height) = image.dimensions();
+            let pixels = image
+                .pixels()
+                .map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+                .collect();
+            (width, height, pixels)
+        }
+    };
+
+    Ok(Environment {
+        width,
+        height,
+        pixels,
+        intensity,
+        rotation,
+    })
+}
+
+/// Tables for importance sampling the map in proportion to luminance over
+/// a grid of at most `MAX_CDF_WIDTH` by `MAX_CDF_HEIGHT` cells, laid out
+/// the way the compute shader reads them:
+///
+/// - the average of the sampled function over the map,
+/// - the grid width and height,
+/// - the marginal CDF over the rows, `height + 1` entries,
+/// - the conditional CDF over the columns of each row, `width + 1` entries
+///   per row.
+///
+/// Each texel is weighted by the sine of its polar angle, since rows near
+/// the poles cover less of the sphere, and each cell averages the texels
+/// whose centers fall into it. Within a cell directions are picked
+/// uniformly, so the shader derives the pdf from the tables alone.
+pub fn build_environment_cdf(environment: &Environment) -> Vec<f32> {
+    let map_width = environment.width as usize;
+    let map_height = environment.height as usize;
+    let width = map_width.min(MAX_CDF_WIDTH);
+    let height = map_height.min(MAX_CDF_HEIGHT);
+
+    let mut cells = vec![0.0f32; width * height];
+    let mut cell_counts = vec![0u32; width * height];
+    for y in 0..map_height {
+        let sin_theta = (std::f32::consts::PI * (y as f32 + 0.5) / map_height as f32).sin();
+        let cell_y = (2 * y + 1) * height / (2 * map_height);
+        for x in 0..map_width {
+            let cell = cell_y * width + (2 * x + 1) * width / (2 * map_width);
+            cells[cell] += luminance(&environment.pixels[y * map_width + x]) * sin_theta;
+            cell_counts[cell] += 1;
+        }
+    }
+
+    let mut data = vec![0.0f32; CDF_HEADER + (height + 1) + height * (width + 1)];
+    let mut row_integrals = vec![0.0f32; height];
+    for (y, row_integral) in row_integrals.iter_mut().enumerate() {
+        let offset = CDF_HEADER + (height + 1) + y * (width + 1);
+        let cdf = &mut data[offset..offset + width + 1];
+        for x in 0..width {
+            let value = cells[y * width + x] / cell_counts[y * width + x] as f32;
+            cdf[x + 1] = cdf[x] + value / width as f32;
+        }
+        *row_integral = cdf[width];
+        normalize_cdf(cdf);
+    }
+
+    let marginal = &mut data[CDF_HEADER..CDF_HEADER + height + 1];
+    for (y, row_integral) in row_integrals.iter().enumerate() {
+        marginal[y + 1] = marginal[y] + row_integral / height as f32;
+    }
+    let integral = marginal[height];
+    normalize_cdf(marginal);
+    data[0] = integral;
+    data[1] = width as f32;
+    data[2] = height as f32;
+
+    data
+}
+
+/// Scales a running sum to end at 1; an all black range becomes uniform.
+fn normalize_cdf(cdf: &mut [f32]) {
+    let count = cdf.len() - 1;
+    let total = cdf[count];
+    for (index, value) in cdf.iter_mut().enumerate() {
+        *value = if total > 0.0 {
+            *value / total
+        } else {
+            index as f32 / count as f32
+        };
+    }
+}
+
+fn luminance(pixel: &[f32; 4]) -> f32 {
+    (0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment(width: u32, height: u32, value: impl Fn(u32, u32) -> f32) -> Environment {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let value = value(x, y);
+                [value, value, value, 1.0]
+            })
+            .collect();
+        Environment {
+            width,
+            height,
+            pixels,
+            intensity: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    /// Grid cell picked by two uniform numbers, as `sample_environment` in
+    /// the compute shader.
+    fn sample_cell(data: &[f32], u: f32, v: f32) -> (usize, usize) {
+        let width = data[1] as usize;
+        let height = data[2] as usize;
+        let find_interval =
+            |cdf: &[f32], value: f32| cdf[..cdf.len() - 1].partition_point(|c| *c <= value) - 1;
+        let marginal = &data[CDF_HEADER..CDF_HEADER + height + 1];
+        let y = find_interval(marginal, u);
+        let offset = CDF_HEADER + height + 1 + y * (width + 1);
+        let x = find_interval(&data[offset..offset + width + 1], v);
+        (x, y)
+    }
+
+    /// Probability of picking a cell, as `environment_pdf` in the compute
+    /// shader before the change of variables to solid angle.
+    fn cell_probability(data: &[f32], x: usize, y: usize) -> f32 {
+        let width = data[1] as usize;
+        let height = data[2] as usize;
+        let offset = CDF_HEADER + height + 1 + y * (width + 1);
+        (data[CDF_HEADER + y + 1] - data[CDF_HEADER + y])
+            * (data[offset + x + 1] - data[offset + x])
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let environment = environment(16, 8, |x, y| 1.0 + (x * 7 + y * 3) as f32 % 5.0);
+        let data = build_environment_cdf(&environment);
+
+        // the solid angle pdf over the sphere, on a grid finer than the map
+        let (columns, rows) = (256, 128);
+        let mut integral = 0.0f64;
+        for row in 0..rows {
+            let v = (row as f32 + 0.5) / rows as f32;
+            let sin_theta = (std::f32::consts::PI * v).sin();
+            for column in 0..columns {
+                let u = (column as f32 + 0.5) / columns as f32;
+                let (x, y) = ((u * 16.0) as usize, (v * 8.0) as usize);
+                let pdf = cell_probability(&data, x, y) * 16.0 * 8.0
+                    / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta);
+                let solid_angle = 2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta
+                    / (columns * rows) as f32;
+                integral += (pdf * solid_angle) as f64;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-4, "integral {}", integral);
+    }
+
+    #[test]
+    fn sampling_matches_pdf() {
+        // one bright texel off the equator and a dim gradient elsewhere
+        let environment = environment(8, 4, |x, y| {
+            if (x, y) == (5, 1) {
+                20.0
+            } else {
+                0.1 * x as f32
+            }
+        });
+        let data = build_environment_cdf(&environment);
+
+        let steps = 512;
+        let mut counts = [0u32; 8 * 4];
+        for i in 0..steps {
+            for j in 0..steps {
+                let u = (i as f32 + 0.5) / steps as f32;
+                let v = (j as f32 + 0.5) / steps as f32;
+                let (x, y) = sample_cell(&data, u, v);
+                counts[y * 8 + x] += 1;
+            }
+        }
+        for y in 0..4 {
+            for x in 0..8 {
+                let frequency = counts[y * 8 + x] as f32 / (steps * steps) as f32;
+                let probability = cell_probability(&data, x, y);
+                assert!(
+                    (frequency - probability).abs() < 0.005,
+                    "cell ({}, {}): sampled {} expected {}",
+                    x,
+                    y,
+                    frequency,
+                    probability
+                );
+            }
+        }
+        // the dark first column is never picked, the bright texel mostly
+        assert_eq!(cell_probability(&data, 0, 2), 0.0);
+        assert!(cell_probability(&data, 5, 1) > 0.5);
+    }
+
+    #[test]
+    fn large_maps_are_averaged_down() {
+        let environment = environment(4096, 2048, |x, _| x as f32);
+        let data = build_environment_cdf(&environment);
+        assert_eq!((data[1], data[2]), (2048.0, 1024.0));
+        assert_eq!(data.len(), CDF_HEADER + 1025 + 1024 * 2049);
+        // every cell averages two texels of a row with a rising value
+        let offset = CDF_HEADER + 1025;
+        let first = data[offset + 1] - data[offset];
+        let last = data[offset + 2048] - data[offset + 2047];
+        assert!((last / first - 4094.5 / 0.5).abs() < 1.0);
+    }
+}
@@ -1,87 +1,169 @@
 use crate::{
-    entity::{Panel, Sphere},
-    settings::{TEXTURE_HEIGHT, TEXTURE_WIDTH, WINDOW_HEIGHT, WINDOW_TOTAL_PIXEL, WINDOW_WIDHT},
+    entity::{Camera, Material, Panel, Sphere},
+    systems::scene::Scene,
 };
 
+/// Indices into `generate_materials`.
+const MATERIAL_WHITE: u32 = 0;
+const MATERIAL_GREEN: u32 = 1;
+const MATERIAL_RED: u32 = 2;
+const MATERIAL_BLUE: u32 = 3;
+const MATERIAL_LIGHT: u32 = 4;
+
+pub fn generate_scene() -> Scene {
+    Scene {
+        camera: generate_camera(),
+        materials: generate_materials(),
+        spheres: generate_sphere_scene(),
+        panels: generate_panel_scene(),
+        lights: generate_lights_scene(),
+        delta_lights: vec![],
+        meshes: vec![],
+        instances: vec![],
+        environment: None,
+    }
+}
+
+/// Cornell box filled with a grid of `sphere_count` small spheres, used to
+/// compare traversal with and without the BVH.
+pub fn generate_benchmark_scene(sphere_count: u32) -> Scene {
+    let per_side = (sphere_count as f32).cbrt().ceil().max(1.0) as u32;
+    let cell = 500.0 / per_side as f32;
+    let radius = cell * 0.35;
+    let mut materials = generate_materials();
+    let mut spheres = Vec::with_capacity(sphere_count as usize);
+    for index in 0..sphere_count {
+        let (x, y, z) = (
+            index % per_side,
+            index / per_side % per_side,
+            index / (per_side * per_side),
+        );
+        let color = [
+            0.2 + 0.6 * x as f32 / per_side as f32,
+            0.2 + 0.6 * y as f32 / per_side as f32,
+            0.2 + 0.6 * z as f32 / per_side as f32,
+        ];
+        materials.push(Material::Lambertian { color });
+        spheres.push(Sphere::new(
+            [
+                50.0 + (x as f32 + 0.5) * cell,
+                50.0 + (y as f32 + 0.5) * cell,
+                -550.0 + (z as f32 + 0.5) * cell,
+            ],
+            (materials.len() - 1) as u32,
+            radius,
+        ));
+    }
+
+    Scene {
+        camera: generate_camera(),
+        materials,
+        spheres,
+        panels: generate_panel_scene(),
+        lights: generate_lights_scene(),
+        delta_lights: vec![],
+        meshes: vec![],
+        instances: vec![],
+        environment: None,
+    }
+}
+
+pub fn generate_camera() -> Camera {
+    // pinhole 800 units in front of a 512 units tall image plane
+    let vfov = 2.0 * (256.0f32 / 800.0).atan().to_degrees();
+    Camera::new(
+        [300.0, 300.0, 800.0],
+        [300.0, 300.0, 0.0],
+        [0.0, 1.0, 0.0],
+        vfov,
+        1.0,
+    )
+}
+
+pub fn generate_materials() -> Vec<Material> {
+    vec![
+        Material::Lambertian {
+            color: [0.75, 0.75, 0.75],
+        },
+        Material::Lambertian {
+            color: [0.12, 0.45, 0.15],
+        },
+        Material::Lambertian {
+            color: [0.65, 0.05, 0.05],
+        },
+        Material::Lambertian {
+            color: [0.0, 0.0, 0.7],
+        },
+        Material::Emissive {
+            emission: [7.0, 7.0, 7.0],
+        },
+    ]
+}
+
 pub fn generate_sphere_scene() -> Vec<Sphere> {
-    let sphere0 = Sphere::new([300.0, 60.0, -160.0], [0.0, 0.0, 0.7], 60.0);
+    let sphere0 = Sphere::new([300.0, 60.0, -160.0], MATERIAL_BLUE, 60.0);
 
     vec![sphere0]
 }
 
 pub fn generate_panel_scene() -> Vec<Panel> {
-    let panel_top = Panel::new(
+    let panel_top = Panel::axis_aligned(
         [0.0, 600.0, -600.0],
         [600.0, 600.0, 0.0],
         [0.0, -1.0, 0.0],
-        [0.75, 0.75, 0.75],
+        MATERIAL_WHITE,
     );
-    let panel_left = Panel::new(
+    let panel_left = Panel::axis_aligned(
         [0.0, 0.0, -600.0],
         [0.0, 600.0, 0.0],
         [1.0, 0.0, 0.0],
-        [0.12, 0.45, 0.15],
+        MATERIAL_GREEN,
     );
-    let panel_back = Panel::new(
+    let panel_back = Panel::axis_aligned(
         [0.0, 0.0, -600.0],
         [600.0, 600.0, -600.0],
         [0.0, 0.0, 1.0],
-        [0.75, 0.75, 0.75],
+        MATERIAL_WHITE,
     );
-    let panel_right = Panel::new(
+    let panel_right = Panel::axis_aligned(
         [600.0, 0.0, -600.0],
         [600.0, 600.0, 0.0],
         [-1.0, 0.0, 0.0],
-        [0.65, 0.05, 0.05],
+        MATERIAL_RED,
     );
-    let panel_bottom = Panel::new(
+    let panel_bottom = Panel::axis_aligned(
         [0.0, 0.0, -600.0],
         [600.0, 0.0, 0.0],
         [0.0, 1.0, 0.0],
-        [0.75, 0.75, 0.75],
+        MATERIAL_WHITE,
     );
 
     vec![panel_top, panel_left, panel_back, panel_right, panel_bottom]
 }
 
 pub fn generate_lights_scene() -> Vec<Panel> {
-    let panel_light = Panel::new(
+    let panel_light = Panel::axis_aligned(
         [225.0, 599.0, -350.0],
         [375.0, 599.0, -200.0],
         [0.0, -1.0, 0.0],
-        [7.0, 7.0, 7.0],
+        MATERIAL_LIGHT,
     );
 
     vec![panel_light]
 }
 
-pub fn generate_input_data() -> Vec<f32> {
-    let mut result = Vec::with_capacity((WINDOW_TOTAL_PIXEL * 2) as usize);
-    for row_num in 0..TEXTURE_HEIGHT as usize {
-        for col_num in 0..TEXTURE_WIDTH as usize {
-            result.push(col_num as f32);
-            result.push(row_num as f32);
-            // for _ in 0..SAMPLES_PER_PIXEL as usize {
-            //     let x = col_num as f32 + rng.gen_range(0.0..1.0);
-            //     let y = WINDOW_HEIGHT as f32 - row_num as f32 - rng.gen_range(0.0..1.0);
-            //     result.push(x - (WINDOW_WIDHT / 2) as f32);
-            //     result.push(y - (WINDOW_HEIGHT / 2) as f32);
-            // }
-        }
-    }
-
-    result
-}
-
-pub fn generate_clip_rect() -> (u32, u32, u32, u32) {
-    let scale = (WINDOW_WIDHT as f32 / TEXTURE_WIDTH as f32)
-        .min(WINDOW_HEIGHT as f32 / TEXTURE_HEIGHT as f32)
-        .max(1.0)
-        .floor();
-    let scaled_width = TEXTURE_WIDTH as f32 * scale;
-    let scaled_height = TEXTURE_HEIGHT as f32 * scale;
-    let x = (WINDOW_WIDHT as f32 - scaled_width) / 2.0;
-    let y = (WINDOW_HEIGHT as f32 - scaled_height) / 2.0;
+pub fn generate_clip_rect(
+    window_size: (u32, u32),
+    texture_size: (u32, u32),
+) -> (u32, u32, u32, u32) {
+    let (window_width, window_height) = window_size;
+    let (texture_width, texture_height) = texture_size;
+    let scale = (window_width as f32 / texture_width as f32)
+        .min(window_height as f32 / texture_height as f32);
+    let scaled_width = texture_width as f32 * scale;
+    let scaled_height = texture_height as f32 * scale;
+    let x = (window_width as f32 - scaled_width) / 2.0;
+    let y = (window_height as f32 - scaled_height) / 2.0;
 
     (
         x as u32,
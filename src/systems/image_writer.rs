@@ -0,0 +1,82 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Whether `path` names a floating-point format that gets the linear
+/// radiance instead of tonemapped colors.
+pub fn is_radiance_path(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("exr" | "hdr")
+    )
+}
+
+/// Path of the EXR file an AOV called `name` is written to next to the
+/// image at `path`, as in `render.albedo.exr` for `render.png`.
+pub fn aov_path(path: &Path, name: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("image");
+    path.with_file_name(format!("{}.{}.exr", stem, name))
+}
+
+/// Writes linear radiance to `path`, as Radiance HDR for `.hdr` files and
+/// OpenEXR otherwise.
+pub fn save_radiance(
+    path: &Path,
+    width: u32,
+    height: u32,
+    radiance: &[[f32; 4]],
+) -> std::io::Result<()> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("hdr") => save_hdr(path, width, height, radiance),
+        _ => save_exr(path, width, height, radiance),
+    }
+}
+
+/// Writes rgba8 pixels to `path`, choosing PPM for `.ppm` files and PNG otherwise.
+pub fn save_image(path: &Path, width: u32, height: u32, rgba: &[u8]) -> std::io::Result<()> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("ppm") => save_ppm(path, width, height, rgba),
+        _ => save_png(path, width, height, rgba),
+    }
+}
+
+fn save_ppm(path: &Path, width: u32, height: u32, rgba: &[u8]) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", width, height)?;
+    for pixel in rgba.chunks_exact(4) {
+        file.write_all(&pixel[..3])?;
+    }
+    file.flush()
+}
+
+fn save_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> std::io::Result<()> {
+    image::save_buffer(path, rgba, width, height, image::ColorType::Rgba8)
+        .map_err(std::io::Error::other)
+}
+
+fn save_exr(path: &Path, width: u32, height: u32, radiance: &[[f32; 4]]) -> std::io::Result<()> {
+    image::save_buffer_with_format(
+        path,
+        bytemuck::cast_slice(radiance),
+        width,
+        height,
+        image::ColorType::Rgba32F,
+        image::ImageFormat::OpenExr,
+    )
+    .map_err(std::io::Error::other)
+}
+
+fn save_hdr(path: &Path, width: u32, height: u32, radiance: &[[f32; 4]]) -> std::io::Result<()> {
+    let pixels: Vec<image::Rgb<f32>> = radiance
+        .iter()
+        .map(|pixel| image::Rgb([pixel[0], pixel[1], pixel[2]]))
+        .collect();
+    image::codecs::hdr::HdrEncoder::new(std::io::BufWriter::new(File::create(path)?))
+        .encode(&pixels, width as usize, height as usize)
+        .map_err(std::io::Error::other)
+}
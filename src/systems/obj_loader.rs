@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use crate::{
+    entity::{Material, Mesh, Triangle, Vertex},
+    systems::math::{cross, length, normalize, sub},
+};
+
+/// Used when a face has no material or the MTL file has no `Kd`.
+const DEFAULT_COLOR: [f32; 3] = [0.75, 0.75, 0.75];
+
+/// Loads every object of a Wavefront OBJ file into one mesh. Faces are
+/// triangulated and vertices without normals get the area weighted average
+/// of their face normals. Each MTL material is appended to `materials` and
+/// referenced from the triangles: a PBR material when it has the `Pr` or
+/// `Pm` extension parameters, a Lambertian one with its diffuse color
+/// otherwise.
+pub fn load_obj(path: &Path, materials: &mut Vec<Material>) -> Result<Mesh, tobj::LoadError> {
+    let (models, obj_materials) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+        },
+    )?;
+    // a missing or broken MTL file only costs the colors
+    let obj_materials = obj_materials.unwrap_or_default();
+    // scene material index of each MTL material, filled on first use
+    let mut material_indices = vec![None; obj_materials.len()];
+    let mut default_index = None;
+
+    let mut mesh = Mesh::default();
+    for model in models {
+        let obj_mesh = model.mesh;
+        let obj_material = obj_mesh.material_id.filter(|id| *id < obj_materials.len());
+        let slot = match obj_material {
+            Some(id) => &mut material_indices[id],
+            None => &mut default_index,
+        };
+        let material = *slot.get_or_insert_with(|| {
+            materials.push(match obj_material {
+                Some(id) => convert_material(&obj_materials[id]),
+                None => Material::Lambertian {
+                    color: DEFAULT_COLOR,
+                },
+            });
+            (materials.len() - 1) as u32
+        });
+
+        let mut part = Mesh::default();
+        let has_normals = obj_mesh.normals.len() == obj_mesh.positions.len();
+        for (index, position) in obj_mesh.positions.chunks_exact(3).enumerate() {
+            let normal = if has_normals {
+                [
+                    obj_mesh.normals[index * 3],
+                    obj_mesh.normals[index * 3 + 1],
+                    obj_mesh.normals[index * 3 + 2],
+                ]
+            } else {
+                [0.0; 3]
+            };
+            part.vertices
+                .push(Vertex::new([position[0], position[1], position[2]], normal));
+        }
+        for face in obj_mesh.indices.chunks_exact(3) {
+            part.triangles
+                .push(Triangle::new([face[0], face[1], face[2]], material));
+        }
+        if !has_normals {
+            generate_normals(&mut part);
+        }
+
+        mesh.append(&part);
+    }
+
+    Ok(mesh)
+}
+
+fn convert_material(material: &tobj::Material) -> Material {
+    let color = material.diffuse.unwrap_or(DEFAULT_COLOR);
+    let parameter = |name: &str| {
+        material
+            .unknown_param
+            .get(name)
+            .and_then(|value| value.trim().parse::<f32>().ok())
+            .map(|value| value.clamp(0.0, 1.0))
+    };
+    match (parameter("Pr"), parameter("Pm")) {
+        (None, None) => Material::Lambertian { color },
+        (roughness, metallic) => Material::Pbr {
+            base_color: color,
+            roughness: roughness.unwrap_or(0.5),
+            metallic: metallic.unwrap_or(0.0),
+        },
+    }
+}
+
+fn generate_normals(mesh: &mut Mesh) {
+    for triangle in &mesh.triangles {
+        let [a, b, c] = triangle.indices.map(|index| {
+            let position = mesh.vertices[index as usize].position;
+            [position[0], position[1], position[2]]
+        });
+        // unnormalized, so larger faces weigh more
+        let face_normal = cross(sub(b, a), sub(c, a));
+        for index in triangle.indices {
+            let normal = &mut mesh.vertices[index as usize].normal;
+            for (value, face_value) in normal.iter_mut().zip(face_normal) {
+                *value += face_value;
+            }
+        }
+    }
+
+    for vertex in mesh.vertices.iter_mut() {
+        let normal = [vertex.normal[0], vertex.normal[1], vertex.normal[2]];
+        if length(normal) > 0.0 {
+            vertex.normal[..3].clone_from_slice(&normalize(normal));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::math::dot;
+
+    #[test]
+    fn triangulates_quads_and_generates_normals() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cube.obj");
+        let mut materials = vec![Material::Dielectric { ior: 1.5 }];
+        let mesh = load_obj(&path, &mut materials).unwrap();
+
+        // six quads sharing the eight corners
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.triangles.len(), 12);
+        assert_eq!(
+            materials[1],
+            Material::Lambertian {
+                color: [0.8, 0.6, 0.2]
+            }
+        );
+        assert!(mesh.triangles.iter().all(|triangle| triangle.material == 1));
+        for vertex in &mesh.vertices {
+            let normal = [vertex.normal[0], vertex.normal[1], vertex.normal[2]];
+            let position = [vertex.position[0], vertex.position[1], vertex.position[2]];
+            assert!((length(normal) - 1.0).abs() < 1e-5);
+            // the cube is centered on the origin, so corners face outwards
+            assert!(dot(normal, position) > 0.0, "{:?}", vertex);
+        }
+    }
+
+    #[test]
+    fn keeps_file_normals() {
+        let path = std::env::temp_dir().join("obj_loader_keeps_file_normals.obj");
+        std::fs::write(
+            &path,
+            "v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nvn 0 -1 0\nf 1//1 2//1 3//1 4//1\n",
+        )
+        .unwrap();
+        let mut materials = Vec::new();
+        let mesh = load_obj(&path, &mut materials);
+        std::fs::remove_file(&path).unwrap();
+        let mesh = mesh.unwrap();
+
+        assert_eq!(mesh.triangles.len(), 2);
+        // the winding points up, the file normal down: the file wins
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal[..3], [0.0, -1.0, 0.0]);
+        }
+        assert_eq!(
+            materials,
+            [Material::Lambertian {
+                color: DEFAULT_COLOR
+            }]
+        );
+    }
+
+    #[test]
+    fn converts_pbr_parameters() {
+        let mut material = tobj::Material {
+            diffuse: Some([0.5, 0.5, 0.5]),
+            ..Default::default()
+        };
+        material.unknown_param.insert("Pm".into(), "1.5".into());
+
+        assert_eq!(
+            convert_material(&material),
+            Material::Pbr {
+                base_color: [0.5, 0.5, 0.5],
+                roughness: 0.5,
+                metallic: 1.0,
+            }
+        );
+    }
+}
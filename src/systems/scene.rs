@@ -0,0 +1,832 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    entity::{Camera, DeltaLight, Material, Mesh, Panel, Sphere},
+    systems::{
+        environment::{load_environment, Environment, MAX_ENVIRONMENT_SIZE},
+        generator::generate_camera,
+        math::{cross, sub},
+        obj_loader::load_obj,
+        transform::Transform,
+    },
+};
+
+/// Entity lists ready to be uploaded to the gpu.
+#[derive(Debug, Clone)]
+pub struct Scene {
+    /// Its aspect is replaced by the render target's when uploaded.
+    pub camera: Camera,
+    /// Referenced by index from every sphere, panel, light and triangle.
+    pub materials: Vec<Material>,
+    pub spheres: Vec<Sphere>,
+    pub panels: Vec<Panel>,
+    pub lights: Vec<Panel>,
+    pub delta_lights: Vec<DeltaLight>,
+    /// Each loaded once, in object space.
+    pub meshes: Vec<Mesh>,
+    pub instances: Vec<MeshInstance>,
+    /// Lights rays that miss everything; a flat gray without one.
+    pub environment: Option<Environment>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Invalid { field: String, message: String },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse(err) => write!(f, "{}", err),
+            SceneError::Invalid { field, message } => write!(f, "{}: {}", field, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default)]
+    camera: Option<CameraDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    panels: Vec<PanelDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    #[serde(default)]
+    point_lights: Vec<PointLightDesc>,
+    #[serde(default)]
+    spot_lights: Vec<SpotLightDesc>,
+    #[serde(default)]
+    directional_lights: Vec<DirectionalLightDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    environment: Option<EnvironmentDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    eye: [f32; 3],
+    target: [f32; 3],
+    #[serde(default = "default_up")]
+    up: [f32; 3],
+    /// Vertical field of view in degrees.
+    fov: f32,
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        color: [f32; 3],
+    },
+    Metal {
+        color: [f32; 3],
+        #[serde(default)]
+        fuzz: f32,
+    },
+    Dielectric {
+        ior: f32,
+    },
+    Emissive {
+        emission: [f32; 3],
+    },
+    Pbr {
+        base_color: [f32; 3],
+        roughness: f32,
+        #[serde(default)]
+        metallic: f32,
+    },
+}
+
+/// Placement of a mesh from `Scene::meshes`.
+#[derive(Debug, Clone)]
+pub struct MeshInstance {
+    pub mesh: usize,
+    pub transform: Transform,
+}
+
+/// Scales, then rotates, then translates an object.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    #[serde(default)]
+    translate: [f32; 3],
+    /// Degrees around the x, y and z axes, applied in that order.
+    #[serde(default)]
+    rotate: [f32; 3],
+    #[serde(default = "default_scale3")]
+    scale: [f32; 3],
+}
+
+fn default_scale3() -> [f32; 3] {
+    [1.0; 3]
+}
+
+/// Spheres only take a `transform` that scales all axes alike.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f32; 3],
+    radius: f32,
+    material: String,
+    #[serde(default)]
+    transform: Option<TransformDesc>,
+}
+
+/// Either an axis-aligned rectangle spanning `min` to `max` and facing along
+/// `normal`, or a parallelogram spanned by the two `edges` from `origin` and
+/// facing along their cross product.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PanelDesc {
+    #[serde(default)]
+    min: Option<[f32; 3]>,
+    #[serde(default)]
+    max: Option<[f32; 3]>,
+    #[serde(default)]
+    normal: Option<[f32; 3]>,
+    #[serde(default)]
+    origin: Option<[f32; 3]>,
+    #[serde(default)]
+    edges: Option<[[f32; 3]; 2]>,
+    material: String,
+    #[serde(default)]
+    transform: Option<TransformDesc>,
+}
+
+/// Shaped like `PanelDesc`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LightDesc {
+    #[serde(default)]
+    min: Option<[f32; 3]>,
+    #[serde(default)]
+    max: Option<[f32; 3]>,
+    #[serde(default)]
+    normal: Option<[f32; 3]>,
+    #[serde(default)]
+    origin: Option<[f32; 3]>,
+    #[serde(default)]
+    edges: Option<[[f32; 3]; 2]>,
+    emission: [f32; 3],
+    #[serde(default)]
+    transform: Option<TransformDesc>,
+}
+
+/// The shape fields shared by `PanelDesc` and `LightDesc`.
+struct PanelShape {
+    min: Option<[f32; 3]>,
+    max: Option<[f32; 3]>,
+    normal: Option<[f32; 3]>,
+    origin: Option<[f32; 3]>,
+    edges: Option<[[f32; 3]; 2]>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointLightDesc {
+    position: [f32; 3],
+    intensity: [f32; 3],
+}
+
+/// Cone half angles in degrees; the light fades out between them.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpotLightDesc {
+    position: [f32; 3],
+    direction: [f32; 3],
+    intensity: [f32; 3],
+    #[serde(default)]
+    inner_angle: f32,
+    outer_angle: f32,
+}
+
+/// Sun-like light shining along `direction` from infinitely far away.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectionalLightDesc {
+    direction: [f32; 3],
+    irradiance: [f32; 3],
+}
+
+/// Wavefront OBJ file, scaled around its origin, moved to `position` and
+/// then placed by `transform`. Meshes naming the same file and material
+/// share their triangles.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    file: PathBuf,
+    #[serde(default)]
+    position: [f32; 3],
+    #[serde(default = "default_scale")]
+    scale: f32,
+    /// Overrides the colors from the MTL file.
+    #[serde(default)]
+    material: Option<String>,
+    #[serde(default)]
+    transform: Option<TransformDesc>,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Equirectangular `.hdr` or `.exr` map, turned by `rotation` degrees around
+/// the y axis.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    file: PathBuf,
+    #[serde(default = "default_scale")]
+    intensity: f32,
+    #[serde(default)]
+    rotation: f32,
+}
+
+pub fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    let source = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+    parse_scene(&source, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Parses a scene description; relative mesh paths start at `base_dir`.
+pub fn parse_scene(source: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let desc: SceneDesc = ron::Options::default()
+        .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+        .from_str(source)
+        .map_err(SceneError::Parse)?;
+
+    let camera = match &desc.camera {
+        Some(camera) => {
+            check_camera(camera)?;
+            Camera::new(camera.eye, camera.target, camera.up, camera.fov, 1.0)
+        }
+        None => generate_camera(),
+    };
+
+    // sorted so that material indices do not depend on hash order
+    let mut material_names: Vec<&String> = desc.materials.keys().collect();
+    material_names.sort();
+    let mut materials = Vec::with_capacity(material_names.len() + desc.lights.len());
+    let mut material_indices = HashMap::new();
+    for name in material_names {
+        let material = check_material(name, &desc.materials[name])?;
+        material_indices.insert(name.as_str(), materials.len() as u32);
+        materials.push(material);
+    }
+
+    let find_material = |field: String, name: &str| match material_indices.get(name) {
+        Some(index) => Ok(*index),
+        None => Err(invalid(field, format!("unknown material `{}`", name))),
+    };
+
+    let mut spheres = Vec::with_capacity(desc.spheres.len());
+    for (index, sphere) in desc.spheres.iter().enumerate() {
+        if sphere.radius <= 0.0 {
+            return Err(invalid(
+                format!("spheres[{}].radius", index),
+                "must be greater than 0".to_string(),
+            ));
+        }
+        let material = find_material(format!("spheres[{}].material", index), &sphere.material)?;
+        let field = format!("spheres[{}].transform", index);
+        let transform = check_transform(&field, &sphere.transform)?;
+        let scale = sphere
+            .transform
+            .as_ref()
+            .map_or([1.0; 3], |transform| transform.scale);
+        if scale.iter().any(|axis| *axis != scale[0]) {
+            return Err(invalid(
+                format!("{}.scale", field),
+                "must be the same along every axis for spheres".to_string(),
+            ));
+        }
+        spheres.push(Sphere::new(
+            transform.transform_point(sphere.center),
+            material,
+            sphere.radius * scale[0],
+        ));
+    }
+
+    let mut panels = Vec::with_capacity(desc.panels.len());
+    for (index, panel) in desc.panels.iter().enumerate() {
+        let field = format!("panels[{}]", index);
+        let material = find_material(format!("{}.material", field), &panel.material)?;
+        let shape = PanelShape {
+            min: panel.min,
+            max: panel.max,
+            normal: panel.normal,
+            origin: panel.origin,
+            edges: panel.edges,
+        };
+        let transform = check_transform(&format!("{}.transform", field), &panel.transform)?;
+        panels.push(check_panel(&field, shape, material)?.transformed(&transform));
+    }
+
+    let mut lights = Vec::with_capacity(desc.lights.len());
+    for (index, light) in desc.lights.iter().enumerate() {
+        let field = format!("lights[{}]", index);
+        materials.push(Material::Emissive {
+            emission: light.emission,
+        });
+        let material = (materials.len() - 1) as u32;
+        let shape = PanelShape {
+            min: light.min,
+            max: light.max,
+            normal: light.normal,
+            origin: light.origin,
+            edges: light.edges,
+        };
+        let transform = check_transform(&format!("{}.transform", field), &light.transform)?;
+        lights.push(check_panel(&field, shape, material)?.transformed(&transform));
+    }
+
+    let mut delta_lights = Vec::new();
+    for light in &desc.point_lights {
+        delta_lights.push(DeltaLight::point(light.position, light.intensity));
+    }
+    for (index, light) in desc.spot_lights.iter().enumerate() {
+        let field = format!("spot_lights[{}]", index);
+        check_direction(&format!("{}.direction", field), light.direction)?;
+        if !(light.outer_angle > 0.0 && light.outer_angle <= 180.0) {
+            return Err(invalid(
+                format!("{}.outer_angle", field),
+                "must be between 0 and 180 degrees".to_string(),
+            ));
+        }
+        if !(0.0..=light.outer_angle).contains(&light.inner_angle) {
+            return Err(invalid(
+                format!("{}.inner_angle", field),
+                "must be between 0 and `outer_angle`".to_string(),
+            ));
+        }
+        delta_lights.push(DeltaLight::spot(
+            light.position,
+            light.direction,
+            light.intensity,
+            light.inner_angle,
+            light.outer_angle,
+        ));
+    }
+    for (index, light) in desc.directional_lights.iter().enumerate() {
+        check_direction(
+            &format!("directional_lights[{}].direction", index),
+            light.direction,
+        )?;
+        delta_lights.push(DeltaLight::directional(light.direction, light.irradiance));
+    }
+
+    let mut meshes = Vec::new();
+    let mut instances = Vec::with_capacity(desc.meshes.len());
+    // index into `meshes` of every file and material loaded so far
+    let mut loaded_meshes = HashMap::new();
+    for (index, mesh_desc) in desc.meshes.iter().enumerate() {
+        let field = format!("meshes[{}]", index);
+        if mesh_desc.scale <= 0.0 {
+            return Err(invalid(
+                format!("{}.scale", field),
+                "must be greater than 0".to_string(),
+            ));
+        }
+        let transform = check_transform(&format!("{}.transform", field), &mesh_desc.transform)?
+            * Transform::translation(mesh_desc.position)
+            * Transform::scaling([mesh_desc.scale; 3]);
+        let material = match &mesh_desc.material {
+            Some(name) => Some(find_material(format!("{}.material", field), name)?),
+            None => None,
+        };
+        let path = base_dir.join(&mesh_desc.file);
+        if let Some(mesh) = loaded_meshes.get(&(path.clone(), material)) {
+            instances.push(MeshInstance {
+                mesh: *mesh,
+                transform,
+            });
+            continue;
+        }
+
+        // the MTL materials are dropped again when overridden
+        let mut mesh_materials = Vec::new();
+        let mut mesh = load_obj(&path, &mut mesh_materials).map_err(|err| {
+            invalid(
+                format!("{}.file", field),
+                format!("cannot load `{}`: {}", path.display(), err),
+            )
+        })?;
+        if mesh.triangles.is_empty() {
+            return Err(invalid(
+                format!("{}.file", field),
+                format!("`{}` has no faces", path.display()),
+            ));
+        }
+        match material {
+            Some(material) => {
+                for triangle in mesh.triangles.iter_mut() {
+                    triangle.material = material;
+                }
+            }
+            None => {
+                let base = materials.len() as u32;
+                for triangle in mesh.triangles.iter_mut() {
+                    triangle.material += base;
+                }
+                materials.extend(mesh_materials);
+            }
+        }
+        loaded_meshes.insert((path, material), meshes.len());
+        instances.push(MeshInstance {
+            mesh: meshes.len(),
+            transform,
+        });
+        meshes.push(mesh);
+    }
+
+    let environment = match &desc.environment {
+        Some(environment_desc) => {
+            if environment_desc.intensity < 0.0 {
+                return Err(invalid(
+                    "environment.intensity".to_string(),
+                    "must not be negative".to_string(),
+                ));
+            }
+            let path = base_dir.join(&environment_desc.file);
+            let environment = load_environment(
+                &path,
+                environment_desc.intensity,
+                environment_desc.rotation.to_radians(),
+            )
+            .map_err(|err| {
+                invalid(
+                    "environment.file".to_string(),
+                    format!("cannot load `{}`: {}", path.display(), err),
+                )
+            })?;
+            if environment.width > MAX_ENVIRONMENT_SIZE || environment.height > MAX_ENVIRONMENT_SIZE
+            {
+                return Err(invalid(
+                    "environment.file".to_string(),
+                    format!(
+                        "must be at most {} pixels wide and high",
+                        MAX_ENVIRONMENT_SIZE
+                    ),
+                ));
+            }
+            Some(environment)
+        }
+        None => None,
+    };
+
+    if spheres.is_empty() && panels.is_empty() && meshes.is_empty() {
+        return Err(invalid(
+            "scene".to_string(),
+            "needs at least one sphere, panel or mesh".to_string(),
+        ));
+    }
+    // light panels bring their own emissive material
+    let has_emissive = materials
+        .iter()
+        .any(|material| matches!(material, Material::Emissive { .. }));
+    if !has_emissive && delta_lights.is_empty() && environment.is_none() {
+        return Err(invalid(
+            "lights".to_string(),
+            "needs at least one light, emissive material or environment".to_string(),
+        ));
+    }
+
+    Ok(Scene {
+        camera,
+        materials,
+        spheres,
+        panels,
+        lights,
+        delta_lights,
+        meshes,
+        instances,
+        environment,
+    })
+}
+
+fn check_camera(camera: &CameraDesc) -> Result<(), SceneError> {
+    if !(camera.fov > 0.0 && camera.fov < 180.0) {
+        return Err(invalid(
+            "camera.fov".to_string(),
+            "must be between 0 and 180 degrees".to_string(),
+        ));
+    }
+    let forward = sub(camera.target, camera.eye);
+    if forward.iter().all(|v| *v == 0.0) {
+        return Err(invalid(
+            "camera.target".to_string(),
+            "must differ from `eye`".to_string(),
+        ));
+    }
+    if cross(forward, camera.up).iter().all(|v| v.abs() < 1e-6) {
+        return Err(invalid(
+            "camera.up".to_string(),
+            "must not be parallel to the view direction".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_direction(field: &str, direction: [f32; 3]) -> Result<(), SceneError> {
+    if direction.iter().all(|v| *v == 0.0) {
+        return Err(invalid(field.to_string(), "must not be zero".to_string()));
+    }
+    Ok(())
+}
+
+fn check_material(name: &str, material: &MaterialDesc) -> Result<Material, SceneError> {
+    let field = || format!("materials.{}", name);
+    Ok(match *material {
+        MaterialDesc::Lambertian { color } => Material::Lambertian { color },
+        MaterialDesc::Metal { color, fuzz } => {
+            if !(0.0..=1.0).contains(&fuzz) {
+                return Err(invalid(
+                    format!("{}.fuzz", field()),
+                    "must be between 0 and 1".to_string(),
+                ));
+            }
+            Material::Metal { color, fuzz }
+        }
+        MaterialDesc::Dielectric { ior } => {
+            if ior <= 0.0 {
+                return Err(invalid(
+                    format!("{}.ior", field()),
+                    "must be greater than 0".to_string(),
+                ));
+            }
+            Material::Dielectric { ior }
+        }
+        MaterialDesc::Emissive { emission } => Material::Emissive { emission },
+        MaterialDesc::Pbr {
+            base_color,
+            roughness,
+            metallic,
+        } => {
+            for (name, value) in [("roughness", roughness), ("metallic", metallic)] {
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(invalid(
+                        format!("{}.{}", field(), name),
+                        "must be between 0 and 1".to_string(),
+                    ));
+                }
+            }
+            Material::Pbr {
+                base_color,
+                roughness,
+                metallic,
+            }
+        }
+    })
+}
+
+/// Builds a panel from either of the shapes `PanelDesc` allows.
+fn check_panel(field: &str, shape: PanelShape, material: u32) -> Result<Panel, SceneError> {
+    match (
+        shape.min,
+        shape.max,
+        shape.normal,
+        shape.origin,
+        shape.edges,
+    ) {
+        (Some(min), Some(max), Some(normal), None, None) => {
+            let axis =
+                match normal.iter().position(|n| n.abs() > 0.0) {
+                    Some(axis) if normal.iter().filter(|n| n.abs() > 0.0).count() == 1 => axis,
+                    _ => return Err(invalid(
+                        format!("{}.normal", field),
+                        "must be aligned to the x, y or z axis, use `origin` and `edges` otherwise"
+                            .to_string(),
+                    )),
+                };
+            for other in (0..3).filter(|&other| other != axis) {
+                if min[other] >= max[other] {
+                    return Err(invalid(
+                        format!("{}.max", field),
+                        "must be greater than `min` along both panel edges".to_string(),
+                    ));
+                }
+            }
+            Ok(Panel::axis_aligned(min, max, normal, material))
+        }
+        (None, None, None, Some(origin), Some([edge_u, edge_v])) => {
+            let panel = Panel::new(origin, edge_u, edge_v, material);
+            if panel.area() <= 0.0 {
+                return Err(invalid(
+                    format!("{}.edges", field),
+                    "must not be zero or parallel".to_string(),
+                ));
+            }
+            Ok(panel)
+        }
+        _ => Err(invalid(
+            field.to_string(),
+            "needs either `min`, `max` and `normal` or `origin` and `edges`".to_string(),
+        )),
+    }
+}
+
+fn check_transform(field: &str, desc: &Option<TransformDesc>) -> Result<Transform, SceneError> {
+    let desc = match desc {
+        Some(desc) => desc,
+        None => return Ok(Transform::identity()),
+    };
+    if desc.scale.iter().any(|axis| *axis <= 0.0) {
+        return Err(invalid(
+            format!("{}.scale", field),
+            "must be greater than 0 along every axis".to_string(),
+        ));
+    }
+
+    Ok(Transform::translation(desc.translate)
+        * Transform::rotation(desc.rotate)
+        * Transform::scaling(desc.scale))
+}
+
+fn invalid(field: String, message: String) -> SceneError {
+    SceneError::Invalid { field, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATERIALS: &str = r#"
+        materials: {
+            "white": Lambertian(color: (0.75, 0.75, 0.75)),
+            "lamp": Emissive(emission: (4.0, 4.0, 4.0)),
+        },
+    "#;
+
+    fn parse(body: &str) -> Result<Scene, SceneError> {
+        parse_scene(&format!("({}{})", MATERIALS, body), Path::new(""))
+    }
+
+    /// The field a scene is rejected for.
+    fn invalid_field(body: &str) -> String {
+        match parse(body) {
+            Err(SceneError::Invalid { field, .. }) => field,
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn parses_entities_and_sorted_materials() {
+        let scene = parse(
+            r#"
+            spheres: [(center: (0.0, 1.0, 0.0), radius: 2.0, material: "white")],
+            panels: [(min: (0.0, 0.0, 0.0), max: (1.0, 0.0, 1.0), normal: (0.0, 1.0, 0.0), material: "lamp")],
+            lights: [(origin: (0.0, 5.0, 0.0), edges: ((0.0, 0.0, 1.0), (1.0, 0.0, 0.0)), emission: (1.0, 1.0, 1.0))],
+            point_lights: [(position: (0.0, 3.0, 0.0), intensity: (1.0, 1.0, 1.0))],
+            "#,
+        )
+        .unwrap();
+
+        // "lamp" sorts before "white", the light panel appends its own
+        assert_eq!(scene.materials.len(), 3);
+        assert!(matches!(scene.materials[0], Material::Emissive { .. }));
+        assert_eq!(scene.spheres[0].material, 1);
+        assert_eq!(scene.spheres[0].radius, 2.0);
+        assert_eq!(scene.panels[0].material, 0);
+        assert_eq!(scene.lights[0].material, 2);
+        assert_eq!(scene.delta_lights.len(), 1);
+        assert!(scene.environment.is_none());
+    }
+
+    #[test]
+    fn transforms_spheres() {
+        let scene = parse(
+            r#"
+            spheres: [(
+                center: (1.0, 0.0, 0.0),
+                radius: 1.0,
+                material: "lamp",
+                transform: (translate: (0.0, 2.0, 0.0), scale: (3.0, 3.0, 3.0)),
+            )],
+            "#,
+        )
+        .unwrap();
+        let sphere = scene.spheres[0];
+        assert_eq!(sphere.radius, 3.0);
+        for (axis, expected) in [3.0, 2.0, 0.0].into_iter().enumerate() {
+            assert!((sphere.position[axis] - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn reports_syntax_and_unknown_fields() {
+        assert!(matches!(
+            parse(r#"spheres: [(center: (0.0, 0.0, 0.0), radius: 1.0"#),
+            Err(SceneError::Parse(_))
+        ));
+        assert!(matches!(
+            parse(
+                r#"spheres: [(center: (0.0, 0.0, 0.0), radius: 1.0, material: "white", color: 1.0)]"#
+            ),
+            Err(SceneError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn reports_the_invalid_field() {
+        let sphere = r#"(center: (0.0, 0.0, 0.0), radius: 1.0, material: "lamp")"#;
+        assert_eq!(
+            invalid_field(&format!(
+                r#"spheres: [{}, (center: (0.0, 0.0, 0.0), radius: 1.0, material: "glass")],"#,
+                sphere
+            )),
+            "spheres[1].material"
+        );
+        assert_eq!(
+            invalid_field(
+                r#"spheres: [(center: (0.0, 0.0, 0.0), radius: 0.0, material: "lamp")],"#
+            ),
+            "spheres[0].radius"
+        );
+        assert_eq!(
+            invalid_field(
+                r#"spheres: [(center: (0.0, 0.0, 0.0), radius: 1.0, material: "lamp", transform: (scale: (1.0, 2.0, 1.0)))],"#
+            ),
+            "spheres[0].transform.scale"
+        );
+        assert_eq!(
+            invalid_field(&format!(
+                r#"spheres: [{}], panels: [(min: (0.0, 0.0, 0.0), max: (1.0, 1.0, 1.0), normal: (1.0, 1.0, 0.0), material: "white")],"#,
+                sphere
+            )),
+            "panels[0].normal"
+        );
+        assert_eq!(
+            invalid_field(&format!(
+                r#"spheres: [{}], panels: [(origin: (0.0, 0.0, 0.0), material: "white")],"#,
+                sphere
+            )),
+            "panels[0]"
+        );
+        assert_eq!(
+            invalid_field(&format!(
+                r#"spheres: [{}], camera: (eye: (0.0, 0.0, 1.0), target: (0.0, 0.0, 0.0), fov: 180.0),"#,
+                sphere
+            )),
+            "camera.fov"
+        );
+        assert_eq!(
+            invalid_field(&format!(
+                r#"spheres: [{}], camera: (eye: (0.0, 1.0, 0.0), target: (0.0, 0.0, 0.0), fov: 40.0),"#,
+                sphere
+            )),
+            "camera.up"
+        );
+        assert_eq!(
+            invalid_field(&format!(
+                r#"spheres: [{}], spot_lights: [(position: (0.0, 0.0, 0.0), direction: (0.0, -1.0, 0.0), intensity: (1.0, 1.0, 1.0), inner_angle: 50.0, outer_angle: 40.0)],"#,
+                sphere
+            )),
+            "spot_lights[0].inner_angle"
+        );
+        assert_eq!(
+            invalid_field(&format!(
+                r#"spheres: [{}], meshes: [(file: "missing.obj")],"#,
+                sphere
+            )),
+            "meshes[0].file"
+        );
+        assert_eq!(invalid_field(""), "scene");
+    }
+
+    #[test]
+    fn requires_a_light() {
+        let source = r#"(
+            materials: {"white": Lambertian(color: (0.5, 0.5, 0.5))},
+            spheres: [(center: (0.0, 0.0, 0.0), radius: 1.0, material: "white")],
+        )"#;
+        match parse_scene(source, Path::new("")) {
+            Err(err @ SceneError::Invalid { .. }) => assert_eq!(
+                err.to_string(),
+                "lights: needs at least one light, emissive material or environment"
+            ),
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
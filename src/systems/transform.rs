@@ -0,0 +1,195 @@
+use std::ops::Mul;
+
+/// Affine transform as a row-major 4x4 matrix acting on column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub matrix: [[f32; 4]; 4],
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        let mut matrix = [[0.0; 4]; 4];
+        for (index, row) in matrix.iter_mut().enumerate() {
+            row[index] = 1.0;
+        }
+        Transform { matrix }
+    }
+
+    pub fn translation(offset: [f32; 3]) -> Self {
+        let mut transform = Transform::identity();
+        for (row, offset) in transform.matrix.iter_mut().zip(offset) {
+            row[3] = offset;
+        }
+        transform
+    }
+
+    pub fn scaling(scale: [f32; 3]) -> Self {
+        let mut transform = Transform::identity();
+        for (index, scale) in scale.into_iter().enumerate() {
+            transform.matrix[index][index] = scale;
+        }
+        transform
+    }
+
+    /// Turns by `degrees` around the x axis, then the y axis, then the z
+    /// axis.
+    pub fn rotation(degrees: [f32; 3]) -> Self {
+        let [x, y, z] = degrees.map(|degree| degree.to_radians().sin_cos());
+        let mut around_x = Transform::identity();
+        around_x.matrix[1][1] = x.1;
+        around_x.matrix[1][2] = -x.0;
+        around_x.matrix[2][1] = x.0;
+        around_x.matrix[2][2] = x.1;
+        let mut around_y = Transform::identity();
+        around_y.matrix[0][0] = y.1;
+        around_y.matrix[0][2] = y.0;
+        around_y.matrix[2][0] = -y.0;
+        around_y.matrix[2][2] = y.1;
+        let mut around_z = Transform::identity();
+        around_z.matrix[0][0] = z.1;
+        around_z.matrix[0][1] = -z.0;
+        around_z.matrix[1][0] = z.0;
+        around_z.matrix[1][1] = z.1;
+        around_z * around_y * around_x
+    }
+
+    /// Inverts the linear part by cofactors and undoes the translation,
+    /// which is all an affine transform needs.
+    pub fn inverse(&self) -> Self {
+        let m = &self.matrix;
+        let cofactor = |row: usize, column: usize| {
+            let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+            let (c0, c1) = ((column + 1) % 3, (column + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let determinant =
+            m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+
+        let mut inverse = Transform::identity();
+        for row in 0..3 {
+            for column in 0..3 {
+                inverse.matrix[row][column] = cofactor(column, row) / determinant;
+            }
+        }
+        let translation = inverse.transform_vector([m[0][3], m[1][3], m[2][3]]);
+        for (row, offset) in inverse.matrix.iter_mut().zip(translation) {
+            row[3] = -offset;
+        }
+        inverse
+    }
+
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let [x, y, z] = self.transform_vector(point);
+        [
+            x + self.matrix[0][3],
+            y + self.matrix[1][3],
+            z + self.matrix[2][3],
+        ]
+    }
+
+    pub fn transform_vector(&self, vector: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|row| {
+            self.matrix[row][0] * vector[0]
+                + self.matrix[row][1] * vector[1]
+                + self.matrix[row][2] * vector[2]
+        })
+    }
+
+    /// Column-major copy, the layout of a WGSL `mat4x4<f32>`.
+    pub fn columns(&self) -> [[f32; 4]; 4] {
+        [0, 1, 2, 3].map(|column| self.matrix.map(|row| row[column]))
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    /// Applies `rhs` first, then `self`.
+    fn mul(self, rhs: Transform) -> Transform {
+        let mut matrix = [[0.0; 4]; 4];
+        for (row, values) in matrix.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..4)
+                    .map(|index| self.matrix[row][index] * rhs.matrix[index][column])
+                    .sum();
+            }
+        }
+        Transform { matrix }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for (actual_value, expected_value) in actual.into_iter().zip(expected) {
+            assert!(
+                (actual_value - expected_value).abs() < 1e-4,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    fn skewed() -> Transform {
+        Transform::translation([1.0, -2.0, 3.0])
+            * Transform::rotation([30.0, -45.0, 60.0])
+            * Transform::scaling([2.0, 0.5, -3.0])
+    }
+
+    #[test]
+    fn inverse_round_trips_points_and_vectors() {
+        let transform = skewed();
+        let inverse = transform.inverse();
+        for point in [[0.0, 0.0, 0.0], [1.0, 2.0, 3.0], [-4.0, 0.5, 7.0]] {
+            assert_near(
+                inverse.transform_point(transform.transform_point(point)),
+                point,
+            );
+            assert_near(
+                transform.transform_point(inverse.transform_point(point)),
+                point,
+            );
+            assert_near(
+                inverse.transform_vector(transform.transform_vector(point)),
+                point,
+            );
+        }
+    }
+
+    #[test]
+    fn inverse_times_transform_is_identity() {
+        let transform = skewed();
+        let identity = Transform::identity();
+        for product in [
+            transform * transform.inverse(),
+            transform.inverse() * transform,
+        ] {
+            for (row, expected) in product.matrix.iter().zip(identity.matrix) {
+                for (value, expected) in row.iter().zip(expected) {
+                    assert!((value - expected).abs() < 1e-5, "{:?}", product);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_of_a_shear() {
+        // not orthogonal, so transposing would not do
+        let mut shear = Transform::identity();
+        shear.matrix[0][1] = 2.0;
+        shear.matrix[2][0] = -1.0;
+        let inverse = shear.inverse();
+        assert_near(inverse.transform_point([2.0, 1.0, -2.0]), [0.0, 1.0, -2.0]);
+    }
+
+    #[test]
+    fn rotation_turns_x_before_z() {
+        let rotation = Transform::rotation([90.0, 0.0, 90.0]);
+        // the x turn leaves x alone, the z turn then takes it to y; the
+        // other order would end up on z
+        assert_near(rotation.transform_vector([1.0, 0.0, 0.0]), [0.0, 1.0, 0.0]);
+    }
+}
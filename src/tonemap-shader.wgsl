@@ -0,0 +1,162 @@
+struct RadianceBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+
+struct ResultBuffer {
+    data: [[stride(4)]] array<u32>;
+};
+
+// three entries per pixel, see `systems::aov`
+struct AovBuffer {
+    data: [[stride(16)]] array<vec4<f32>>;
+};
+
+struct TonemapConfig {
+    pixel_count: u32;
+    // 0 linear, 1 reinhard, 2 aces, 3 agx, as `settings::Tonemap`
+    operator: u32;
+    exposure: f32;
+    // 0 for the beauty image, then the AOVs in the order of `aov::Aov`
+    view: u32;
+    // camera to target, the scale depth and position are shown at
+    focus_distance: f32;
+    width: u32;
+    // pixels per row of the output, padded for copies into a texture
+    row_pitch: u32;
+    placholder0: u32;
+};
+
+[[group(0), binding(0)]]
+var<storage, read> radiance_list: RadianceBuffer;
+[[group(0), binding(1)]]
+var<storage, read_write> output_list: ResultBuffer;
+[[group(0), binding(2)]]
+var<storage, read> aov_list: AovBuffer;
+
+[[group(1), binding(0)]]
+var<uniform> tonemap_config: TonemapConfig;
+
+// workgroups along x of a dispatch, larger images take more rows of them,
+// as `tracer::DISPATCH_ROW_GROUPS`
+let DISPATCH_ROW_GROUPS: u32 = 32768u;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + luminance(color));
+}
+
+// Stephen Hill's fit, the matrices are given by rows
+fn tonemap_aces(color: vec3<f32>) -> vec3<f32> {
+    let input_mat = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.35458, 0.04823),
+        vec3<f32>(0.07600, 0.90834, 0.01566),
+        vec3<f32>(0.02840, 0.13383, 0.83777),
+    );
+    let output_mat = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.53108, -0.07367),
+        vec3<f32>(-0.10208, 1.10813, -0.00605),
+        vec3<f32>(-0.00327, -0.07276, 1.07602),
+    );
+    let v = color * input_mat;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return (a / b) * output_mat;
+}
+
+// Benjamin Wrensch's fit of the AgX base look, the matrices are given by
+// columns
+fn tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+    let inset_mat = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset_mat = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let encoded = clamp(
+        log2(max(inset_mat * color, vec3<f32>(1e-10, 1e-10, 1e-10))),
+        vec3<f32>(min_ev, min_ev, min_ev),
+        vec3<f32>(max_ev, max_ev, max_ev),
+    );
+    let x = (encoded - min_ev) / (max_ev - min_ev);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    // the curve ends in display gamma, undone here for the sRGB encoding
+    return pow(max(outset_mat * curve, vec3<f32>(0.0, 0.0, 0.0)), vec3<f32>(2.2, 2.2, 2.2));
+}
+
+fn srgb_encode(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4, 1.0 / 2.4, 1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308, 0.0031308, 0.0031308));
+}
+
+// a stable color per index, black for misses
+fn index_color(index: f32) -> vec3<f32> {
+    if (index < 0.0) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    var hash: u32 = u32(index) * 747796405u + 2891336453u;
+    hash = ((hash >> ((hash >> 28u) + 4u)) ^ hash) * 277803737u;
+    hash = (hash >> 22u) ^ hash;
+    return vec3<f32>(f32(hash & 255u), f32((hash >> 8u) & 255u), f32((hash >> 16u) & 255u)) / 255.0;
+}
+
+// AOVs in display colors, already encoded
+fn aov_color(pixel_index: u32, view: u32) -> vec3<f32> {
+    let albedo_depth = aov_list.data[pixel_index * 3u];
+    let normal_material = aov_list.data[pixel_index * 3u + 1u];
+    let position_object = aov_list.data[pixel_index * 3u + 2u];
+    let scale = max(tonemap_config.focus_distance, 0.000001);
+    if (view == 1u) {
+        return srgb_encode(albedo_depth.xyz);
+    } else if (view == 2u) {
+        return normal_material.xyz * 0.5 + 0.5;
+    } else if (view == 3u) {
+        // half gray at the camera target
+        let depth = albedo_depth.w / scale;
+        return vec3<f32>(1.0, 1.0, 1.0) * depth / (1.0 + depth);
+    } else if (view == 4u) {
+        return fract(position_object.xyz / scale);
+    } else if (view == 5u) {
+        return index_color(normal_material.w);
+    }
+    return index_color(position_object.w);
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(global_invocation_id)]] gi_id: vec3<u32>) {
+    let pixel_index = gi_id.y * DISPATCH_ROW_GROUPS * 64u + gi_id.x;
+    if (pixel_index >= tonemap_config.pixel_count) {
+        return;
+    }
+    let output_index = pixel_index / tonemap_config.width * tonemap_config.row_pitch + pixel_index % tonemap_config.width;
+    if (tonemap_config.view != 0u) {
+        let color = clamp(aov_color(pixel_index, tonemap_config.view), vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0)) * 255.0 + 0.5;
+        output_list.data[output_index] = u32(color.z) | u32(color.y) << 8u | u32(color.x) << 16u | 255u << 24u;
+        return;
+    }
+    let radiance = max(radiance_list.data[pixel_index].xyz, vec3<f32>(0.0, 0.0, 0.0)) * exp2(tonemap_config.exposure);
+
+    var mapped: vec3<f32> = radiance;
+    if (tonemap_config.operator == 1u) {
+        mapped = tonemap_reinhard(radiance);
+    } else if (tonemap_config.operator == 2u) {
+        mapped = tonemap_aces(radiance);
+    } else if (tonemap_config.operator == 3u) {
+        mapped = tonemap_agx(radiance);
+    }
+
+    let color = srgb_encode(clamp(mapped, vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0))) * 255.0 + 0.5;
+    output_list.data[output_index] = u32(color.z) | u32(color.y) << 8u | u32(color.x) << 16u | 255u << 24u;
+}
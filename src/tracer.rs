@@ -0,0 +1,911 @@
+use bytemuck::Pod;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    denoiser::{DenoiseBuffers, Denoiser},
+    entity::{Camera, DeltaLight, Instance, MaterialData, Mesh, Panel, Sphere, Triangle, Vertex},
+    settings::{Denoise, Settings, Tonemap},
+    systems::{
+        aov::{View, AOV_STRIDE},
+        bvh::{build_bvh, Bvh, BvhNode, BVH_STACK_SIZE},
+        emitters::{build_emitters, Emitter},
+        environment::build_environment_cdf,
+        scene::Scene,
+    },
+};
+
+const CONFIG_WIDTH: usize = 1;
+const CONFIG_HEIGHT: usize = 2;
+const CONFIG_FRAME_INDEX: usize = 8;
+const CONFIG_AOV: usize = 18;
+/// Workgroups along x of every per-pixel dispatch; larger images take more
+/// rows of them along y. The shaders derive the pixel index from the same
+/// value.
+pub const DISPATCH_ROW_GROUPS: u32 = 32768;
+/// Storage buffers bound to the compute shader: three per-pixel buffers and
+/// twelve scene lists.
+const STORAGE_BUFFER_COUNT: u32 = 15;
+
+pub struct Tracer {
+    pixel_buffers: PixelBuffers,
+    config_buffer: wgpu::Buffer,
+    config_buffer_data: Vec<u32>,
+    camera: Camera,
+    camera_buffer: wgpu::Buffer,
+    frame_index: u32,
+    compute_pipeline: wgpu::ComputePipeline,
+    compute_bindgroup0_layout: wgpu::BindGroupLayout,
+    compute_bindgroup1: wgpu::BindGroup,
+    tonemap_pipeline: wgpu::ComputePipeline,
+    tonemap_bindgroup0_layout: wgpu::BindGroupLayout,
+    tonemap_bindgroup1: wgpu::BindGroup,
+    tonemap_buffer: wgpu::Buffer,
+    tonemap: Tonemap,
+    exposure: f32,
+    view: View,
+    aov: bool,
+    denoiser: Denoiser,
+    denoise: Denoise,
+    work_group_count: (u32, u32),
+}
+
+impl Tracer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &Settings,
+        scene: &Scene,
+    ) -> Self {
+        let compute_shader_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Compute-Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                format!(
+                    "let BVH_STACK_SIZE: u32 = {}u;\n{}",
+                    BVH_STACK_SIZE,
+                    include_str!("compute-shader.wgsl")
+                )
+                .into(),
+            ),
+        });
+
+        let sphere_buffer_data = &scene.spheres;
+        let panel_buffer_data = &scene.panels;
+        let light_buffer_data = &scene.lights;
+        let mut mesh_data = Mesh::default();
+        let mut mesh_ranges = Vec::with_capacity(scene.meshes.len());
+        for mesh in &scene.meshes {
+            mesh_ranges.push((
+                mesh_data.triangles.len() as u32,
+                mesh.triangles.len() as u32,
+            ));
+            mesh_data.append(mesh);
+        }
+        let mut instances: Vec<Instance> = scene
+            .instances
+            .iter()
+            .map(|instance| {
+                let (first_triangle, triangle_count) = mesh_ranges[instance.mesh];
+                Instance::new(&instance.transform, first_triangle, triangle_count)
+            })
+            .collect();
+        let entity_buffers = [
+            create_entity_buffer(device, "Sphere-Buffer", sphere_buffer_data),
+            create_entity_buffer(device, "Panel-Buffer", panel_buffer_data),
+            create_entity_buffer(device, "Light-Buffer", light_buffer_data),
+            create_entity_buffer(device, "Vertex-Buffer", &mesh_data.vertices),
+            create_entity_buffer(device, "Triangle-Buffer", &mesh_data.triangles),
+            create_entity_buffer(device, "Delta-Light-Buffer", &scene.delta_lights),
+        ];
+
+        // an empty hierarchy makes the shader fall back to testing every entity
+        let bvh = if settings.use_bvh {
+            build_bvh(
+                sphere_buffer_data,
+                panel_buffer_data,
+                light_buffer_data,
+                &mesh_data,
+                &mut instances,
+            )
+        } else {
+            Bvh::default()
+        };
+        let instance_buffer = create_entity_buffer(device, "Instance-Buffer", &instances);
+        let material_buffer_data: Vec<MaterialData> = scene
+            .materials
+            .iter()
+            .map(|material| material.data())
+            .collect();
+        let material_buffer =
+            create_entity_buffer(device, "Material-Buffer", &material_buffer_data);
+        // without an environment the shader never reads these placeholders
+        let (environment_size, environment_pixels, environment_cdf) = match &scene.environment {
+            Some(environment) => (
+                (environment.width, environment.height),
+                environment.pixels.clone(),
+                build_environment_cdf(environment),
+            ),
+            None => ((1, 1), vec![[0.0; 4]], vec![]),
+        };
+        let environment_texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Environment-Texture"),
+                size: wgpu::Extent3d {
+                    width: environment_size.0,
+                    height: environment_size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            bytemuck::cast_slice(&environment_pixels),
+        );
+        let environment_view =
+            environment_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let environment_cdf_buffer =
+            create_entity_buffer(device, "Environment-Cdf-Buffer", &environment_cdf);
+
+        let (emitters, emitter_power) = build_emitters(
+            &scene.materials,
+            sphere_buffer_data,
+            panel_buffer_data,
+            light_buffer_data,
+            &mesh_data,
+            &instances,
+        );
+        let emitter_buffer = create_entity_buffer(device, "Emitter-Buffer", &emitters);
+
+        let bvh_buffers = [
+            create_entity_buffer(device, "Bvh-Node-Buffer", &bvh.nodes),
+            create_entity_buffer(device, "Bvh-Primitive-Buffer", &bvh.primitives),
+        ];
+
+        let aov = settings.aov_enabled();
+        let config_buffer_data = vec![
+            settings.seed,
+            settings.width,
+            settings.height,
+            settings.spp,
+            sphere_buffer_data.len() as u32,
+            panel_buffer_data.len() as u32,
+            light_buffer_data.len() as u32,
+            settings.max_depth,
+            0u32, // frame index
+            instances.len() as u32,
+            bvh.nodes.len() as u32,
+            settings.russian_roulette_depth,
+            scene.delta_lights.len() as u32,
+            scene.environment.is_some() as u32,
+            scene
+                .environment
+                .as_ref()
+                .map_or(0.0f32, |environment| environment.intensity)
+                .to_bits(),
+            scene
+                .environment
+                .as_ref()
+                .map_or(0.0f32, |environment| environment.rotation)
+                .to_bits(),
+            emitters.len() as u32,
+            emitter_power.to_bits(),
+            aov as u32,
+            settings.sampler as u32,
+        ];
+        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&config_buffer_data),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mut camera = scene.camera;
+        camera.aspect = settings.width as f32 / settings.height as f32;
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera-Buffer"),
+            contents: bytemuck::bytes_of(&camera),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+
+        // sized per element, so the same layout fits any resolution
+        let pixel_layout_entry =
+            |binding: u32, element_size: u64, read_only: bool| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only },
+                    has_dynamic_offset: false,
+                    min_binding_size: std::num::NonZeroU64::new(element_size),
+                },
+                count: None,
+            };
+        let compute_bindgroup0_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    pixel_layout_entry(0, 16, false),
+                    pixel_layout_entry(1, 16, false),
+                    pixel_layout_entry(2, 16, false),
+                ],
+            });
+        let tonemap_bindgroup0_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    pixel_layout_entry(0, 16, true),
+                    pixel_layout_entry(1, 4, false),
+                    pixel_layout_entry(2, 16, true),
+                ],
+            });
+        let mut denoiser = Denoiser::new(device, camera);
+        denoiser.temporal = settings.denoise == Denoise::Temporal;
+        let pixel_buffers = PixelBuffers::new(
+            device,
+            &compute_bindgroup0_layout,
+            &tonemap_bindgroup0_layout,
+            settings.width,
+            settings.height,
+            aov,
+            (settings.denoise != Denoise::Off).then_some(&denoiser),
+        );
+        let compute_bindgroup1_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    entity_layout_entry(0, std::mem::size_of::<Sphere>()),
+                    entity_layout_entry(1, std::mem::size_of::<Panel>()),
+                    entity_layout_entry(2, std::mem::size_of::<Panel>()),
+                    entity_layout_entry(5, std::mem::size_of::<Vertex>()),
+                    entity_layout_entry(6, std::mem::size_of::<Triangle>()),
+                    entity_layout_entry(7, std::mem::size_of::<BvhNode>()),
+                    entity_layout_entry(8, std::mem::size_of::<u32>()),
+                    entity_layout_entry(9, std::mem::size_of::<MaterialData>()),
+                    entity_layout_entry(10, std::mem::size_of::<DeltaLight>()),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 11,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    entity_layout_entry(12, std::mem::size_of::<f32>()),
+                    entity_layout_entry(13, std::mem::size_of::<Emitter>()),
+                    entity_layout_entry(14, std::mem::size_of::<Instance>()),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(
+                                std::num::NonZeroU64::new(
+                                    (config_buffer_data.len() * std::mem::size_of::<u32>()) as u64,
+                                )
+                                .unwrap(),
+                            ),
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: std::num::NonZeroU64::new(
+                                std::mem::size_of::<Camera>() as u64,
+                            ),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let compute_bindgroup1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Entity"),
+            layout: &compute_bindgroup1_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: entity_buffers[0].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: entity_buffers[1].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: entity_buffers[2].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: config_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: entity_buffers[3].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: entity_buffers[4].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: bvh_buffers[0].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: bvh_buffers[1].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: entity_buffers[5].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&environment_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: environment_cdf_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: emitter_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&compute_bindgroup0_layout, &compute_bindgroup1_layout],
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout),
+            module: &compute_shader_module,
+            entry_point: "main",
+        });
+
+        let tonemap_shader_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap-Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("tonemap-shader.wgsl").into()),
+        });
+        let tonemap_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap-Buffer"),
+            contents: bytemuck::cast_slice(&tonemap_buffer_data(
+                pixel_buffers.size,
+                settings.tonemap,
+                settings.exposure,
+                View::Beauty,
+                &camera,
+            )),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+        let tonemap_bindgroup1_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: std::num::NonZeroU64::new(32),
+                    },
+                    count: None,
+                }],
+            });
+        let tonemap_bindgroup1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap"),
+            layout: &tonemap_bindgroup1_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: tonemap_buffer.as_entire_binding(),
+            }],
+        });
+        let tonemap_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&tonemap_bindgroup0_layout, &tonemap_bindgroup1_layout],
+                push_constant_ranges: &[],
+            });
+        let tonemap_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&tonemap_pipeline_layout),
+            module: &tonemap_shader_module,
+            entry_point: "main",
+        });
+
+        Tracer {
+            work_group_count: pixel_buffers.work_group_count(),
+            pixel_buffers,
+            config_buffer,
+            config_buffer_data,
+            camera,
+            camera_buffer,
+            frame_index: 0,
+            compute_pipeline,
+            compute_bindgroup0_layout,
+            compute_bindgroup1,
+            tonemap_pipeline,
+            tonemap_bindgroup0_layout,
+            tonemap_bindgroup1,
+            tonemap_buffer,
+            tonemap: settings.tonemap,
+            exposure: settings.exposure,
+            view: View::Beauty,
+            aov,
+            denoiser,
+            denoise: settings.denoise,
+        }
+    }
+
+    /// Buffer holding one packed bgra8 `u32` per pixel, row-major, the
+    /// tonemapped radiance. Rows are `result_bytes_per_row` apart.
+    pub fn result_buffer(&self) -> &wgpu::Buffer {
+        &self.pixel_buffers.result_buffer
+    }
+
+    /// Row stride of `result_buffer`, padded to what copies into a texture
+    /// require.
+    pub fn result_bytes_per_row(&self) -> u32 {
+        result_row_pitch(self.pixel_buffers.size.0) * 4
+    }
+
+    /// Buffer holding the linear radiance estimate of each pixel as a
+    /// `vec4<f32>` with alpha 1, row-major.
+    pub fn radiance_buffer(&self) -> &wgpu::Buffer {
+        &self.pixel_buffers.radiance_buffer
+    }
+
+    /// Buffer holding `AOV_STRIDE` `vec4<f32>` per pixel, row-major, filled
+    /// by the first frame after a reset. A single pixel unless AOVs are
+    /// enabled.
+    pub fn aov_buffer(&self) -> &wgpu::Buffer {
+        &self.pixel_buffers.aov_buffer
+    }
+
+    /// Reallocates every per-pixel buffer for a new resolution, matches the
+    /// camera aspect to it and restarts accumulation.
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.pixel_buffers = PixelBuffers::new(
+            device,
+            &self.compute_bindgroup0_layout,
+            &self.tonemap_bindgroup0_layout,
+            width,
+            height,
+            self.aov,
+            (self.denoise != Denoise::Off).then_some(&self.denoiser),
+        );
+        self.work_group_count = self.pixel_buffers.work_group_count();
+        self.config_buffer_data[CONFIG_WIDTH] = width;
+        self.config_buffer_data[CONFIG_HEIGHT] = height;
+
+        // also refreshes the pixel count of the tonemap pass
+        let mut camera = self.camera;
+        camera.aspect = width as f32 / height as f32;
+        self.set_camera(queue, camera);
+    }
+
+    pub fn tonemap(&self) -> (Tonemap, f32) {
+        (self.tonemap, self.exposure)
+    }
+
+    /// Changes the display transform from the next frame on; the radiance
+    /// and accumulation are left alone.
+    pub fn set_tonemap(&mut self, queue: &wgpu::Queue, tonemap: Tonemap, exposure: f32) {
+        self.tonemap = tonemap;
+        self.exposure = exposure;
+        self.write_tonemap_buffer(queue);
+    }
+
+    pub fn denoise(&self) -> Denoise {
+        self.denoise
+    }
+
+    /// Switches the denoiser. Turning it on the first time also turns on
+    /// the AOVs it needs, which restarts accumulation, and fails with a
+    /// message ready to print when they do not fit on the device.
+    pub fn set_denoise(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        denoise: Denoise,
+    ) -> Result<(), String> {
+        if denoise != Denoise::Off && self.pixel_buffers.denoise_buffers.is_none() {
+            let (width, height) = self.pixel_buffers.size;
+            check_trace_size(&device.limits(), width, height, true)?;
+            self.denoise = denoise;
+            self.aov = true;
+            self.config_buffer_data[CONFIG_AOV] = 1;
+            self.resize(device, queue, width, height);
+        }
+        self.denoise = denoise;
+        self.denoiser.temporal = denoise == Denoise::Temporal;
+        Ok(())
+    }
+
+    /// Whether the AOVs are written, either asked for or for the denoiser.
+    pub fn aov_enabled(&self) -> bool {
+        self.aov
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    /// Shows an AOV instead of the beauty image. Only the beauty image is
+    /// available unless AOVs are enabled.
+    pub fn set_view(&mut self, queue: &wgpu::Queue, view: View) {
+        self.view = if self.aov { view } else { View::Beauty };
+        self.write_tonemap_buffer(queue);
+    }
+
+    fn write_tonemap_buffer(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.tonemap_buffer,
+            0,
+            bytemuck::cast_slice(&tonemap_buffer_data(
+                self.pixel_buffers.size,
+                self.tonemap,
+                self.exposure,
+                self.view,
+                &self.camera,
+            )),
+        );
+    }
+
+    /// Number of frames summed into the accumulation buffer so far.
+    pub fn frame_index(&self) -> u32 {
+        self.frame_index
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    /// Uploads a new viewpoint; accumulation restarts with the next frame.
+    pub fn set_camera(&mut self, queue: &wgpu::Queue, camera: Camera) {
+        self.camera = camera;
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&self.camera));
+        self.write_tonemap_buffer(queue);
+        self.reset_accumulation();
+    }
+
+    /// Makes the next frame overwrite the accumulation buffer instead of
+    /// adding to it.
+    pub fn reset_accumulation(&mut self) {
+        self.frame_index = 0;
+    }
+
+    /// Traces one more frame, refreshes `radiance_buffer` with the running
+    /// average of every frame since the last reset, denoises it if enabled
+    /// and tonemaps it into `result_buffer`.
+    pub fn trace(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        self.config_buffer_data[CONFIG_FRAME_INDEX] = self.frame_index;
+        queue.write_buffer(
+            &self.config_buffer,
+            0,
+            bytemuck::cast_slice(&self.config_buffer_data),
+        );
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Compute-Pass"),
+            });
+            compute_pass.set_pipeline(&self.compute_pipeline);
+            compute_pass.set_bind_group(0, &self.pixel_buffers.bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.compute_bindgroup1, &[]);
+            compute_pass.dispatch(self.work_group_count.0, self.work_group_count.1, 1);
+        }
+
+        if let (Denoise::Spatial | Denoise::Temporal, Some(denoise_buffers)) =
+            (self.denoise, &self.pixel_buffers.denoise_buffers)
+        {
+            self.denoiser.denoise(
+                queue,
+                encoder,
+                denoise_buffers,
+                &self.pixel_buffers.aov_buffer,
+                self.camera,
+                self.frame_index,
+            );
+        }
+        self.frame_index += 1;
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Tonemap-Pass"),
+        });
+        compute_pass.set_pipeline(&self.tonemap_pipeline);
+        compute_pass.set_bind_group(0, &self.pixel_buffers.tonemap_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.tonemap_bindgroup1, &[]);
+        compute_pass.dispatch(self.work_group_count.0, self.work_group_count.1, 1);
+    }
+}
+
+/// Buffers with one entry per pixel, bound as group 0.
+struct PixelBuffers {
+    radiance_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+    _accumulation_buffer: wgpu::Buffer,
+    aov_buffer: wgpu::Buffer,
+    denoise_buffers: Option<DenoiseBuffers>,
+    bind_group: wgpu::BindGroup,
+    tonemap_bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+    total_pixel: u64,
+}
+
+impl PixelBuffers {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        tonemap_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+        aov: bool,
+        denoiser: Option<&Denoiser>,
+    ) -> Self {
+        let total_pixel = width as u64 * height as u64;
+
+        // averaged radiance per pixel, rgb + alpha
+        let radiance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Radiance-Buffer"),
+            size: total_pixel * 16,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let result_buffer_data =
+            vec![0u8; (result_row_pitch(width) as u64 * height as u64 * 4) as usize]; // bgra8usnormsRGB
+        let result_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Res-Buffer"),
+            contents: bytemuck::cast_slice(&result_buffer_data),
+            usage: wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::VERTEX,
+        });
+
+        // running radiance sum per pixel, rgb + unused w
+        let accumulation_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accumulation-Buffer"),
+            size: total_pixel * 16,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // bindings cannot be empty, without AOVs one pixel stands in
+        let aov_pixel = if aov { total_pixel } else { 1 };
+        let aov_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Aov-Buffer"),
+            size: aov_pixel * AOV_STRIDE as u64 * 16,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Compute-Bindgroup"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: radiance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: aov_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let tonemap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap-Bindgroup"),
+            layout: tonemap_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: radiance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: result_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: aov_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let denoise_buffers = denoiser.map(|denoiser| {
+            DenoiseBuffers::new(
+                device,
+                denoiser,
+                &radiance_buffer,
+                &aov_buffer,
+                (width, height),
+            )
+        });
+
+        PixelBuffers {
+            radiance_buffer,
+            result_buffer,
+            _accumulation_buffer: accumulation_buffer,
+            aov_buffer,
+            denoise_buffers,
+            bind_group,
+            tonemap_bind_group,
+            size: (width, height),
+            total_pixel,
+        }
+    }
+
+    fn work_group_count(&self) -> (u32, u32) {
+        work_group_count(self.total_pixel)
+    }
+}
+
+/// Uniform data of the tonemap pass: the pixel count, the operator, the
+/// exposure bits, the view, the camera's distance to its target that depth
+/// and position are displayed relative to, then the width and padded row
+/// pitch of the result, padded to 32 bytes.
+fn tonemap_buffer_data(
+    size: (u32, u32),
+    tonemap: Tonemap,
+    exposure: f32,
+    view: View,
+    camera: &Camera,
+) -> [u32; 8] {
+    let focus_distance = (0..3)
+        .map(|axis| (camera.target[axis] - camera.eye[axis]).powi(2))
+        .sum::<f32>()
+        .sqrt();
+    [
+        size.0 * size.1,
+        tonemap as u32,
+        exposure.to_bits(),
+        view.index(),
+        focus_distance.to_bits(),
+        size.0,
+        result_row_pitch(size.0),
+        0,
+    ]
+}
+
+/// Pixels per row of the result buffer, `width` rounded up so that a row
+/// spans a multiple of `COPY_BYTES_PER_ROW_ALIGNMENT`.
+fn result_row_pitch(width: u32) -> u32 {
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT / 4;
+    width.div_ceil(alignment) * alignment
+}
+
+/// Workgroups to dispatch for one invocation per pixel in groups of 64,
+/// along x and y.
+pub fn work_group_count(total_pixel: u64) -> (u32, u32) {
+    let groups = total_pixel.div_ceil(64) as u32;
+    (
+        groups.min(DISPATCH_ROW_GROUPS),
+        groups.div_ceil(DISPATCH_ROW_GROUPS),
+    )
+}
+
+/// Whether the per-pixel buffers and dispatches of a `width` by `height`
+/// trace, with or without AOVs, fit within `limits`, the error is a message
+/// ready to print.
+pub fn check_trace_size(
+    limits: &wgpu::Limits,
+    width: u32,
+    height: u32,
+    aov: bool,
+) -> Result<(), String> {
+    let total_pixel = width as u64 * height as u64;
+    // the widest per-pixel buffers, the AOVs or the radiance and
+    // accumulation with a vec4<f32> per pixel
+    let binding_size = total_pixel * if aov { AOV_STRIDE as u64 * 16 } else { 16 };
+    if binding_size > limits.max_storage_buffer_binding_size as u64 {
+        return Err(format!(
+            "Trace size {}x{}{} needs {} MiB buffers, the device allows at most {} MiB",
+            width,
+            height,
+            if aov { " with AOVs" } else { "" },
+            binding_size >> 20,
+            limits.max_storage_buffer_binding_size >> 20
+        ));
+    }
+    if work_group_count(total_pixel).1 > limits.max_compute_workgroups_per_dimension {
+        return Err(format!(
+            "Trace size {}x{} needs more workgroups than the device can dispatch",
+            width, height
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the environment map of `scene` fits in a texture within
+/// `limits`, the error is a message ready to print.
+pub fn check_environment_size(limits: &wgpu::Limits, scene: &Scene) -> Result<(), String> {
+    match &scene.environment {
+        Some(environment)
+            if environment.width > limits.max_texture_dimension_2d
+                || environment.height > limits.max_texture_dimension_2d =>
+        {
+            Err(format!(
+                "Environment map {}x{} is larger than the {} pixels the device allows",
+                environment.width, environment.height, limits.max_texture_dimension_2d
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Device limits the tracer needs: the downlevel defaults every adapter
+/// offers, raised to the storage buffers the compute shader binds and to
+/// the largest buffers and textures the adapter supports. Fails with a
+/// message ready to print when the adapter has too few storage buffers.
+pub fn required_limits(adapter: &wgpu::Adapter) -> Result<wgpu::Limits, String> {
+    let supported = adapter.limits();
+    if supported.max_storage_buffers_per_shader_stage < STORAGE_BUFFER_COUNT {
+        return Err(format!(
+            "The adapter supports {} storage buffers per shader stage, the tracer needs {}",
+            supported.max_storage_buffers_per_shader_stage, STORAGE_BUFFER_COUNT
+        ));
+    }
+    Ok(wgpu::Limits {
+        max_storage_buffers_per_shader_stage: STORAGE_BUFFER_COUNT,
+        max_storage_buffer_binding_size: supported.max_storage_buffer_binding_size,
+        max_texture_dimension_2d: supported.max_texture_dimension_2d,
+        ..wgpu::Limits::downlevel_defaults()
+    })
+}
+
+/// Uploads an entity list as a read-only storage buffer. Bindings cannot be
+/// empty, so an empty list still gets one zeroed element; the shader only
+/// reads as many entries as the counts in the config buffer.
+fn create_entity_buffer<T: Pod>(device: &wgpu::Device, label: &str, data: &[T]) -> wgpu::Buffer {
+    let placeholder = [T::zeroed()];
+    let data = if data.is_empty() {
+        &placeholder[..]
+    } else {
+        data
+    };
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::cast_slice(data),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    })
+}
+
+fn entity_layout_entry(binding: u32, element_size: usize) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: std::num::NonZeroU64::new(element_size as u64),
+        },
+        count: None,
+    }
+}